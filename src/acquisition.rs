@@ -0,0 +1,237 @@
+use crate::averaging::AveragingMode;
+use crate::correction::DcMode;
+use crate::detector::DetectedSignal;
+use crate::error::{io, Error, Result};
+use crate::source::{DataSupplier, RowStats, Statistics};
+use crate::sweep::Sweep;
+use crate::window::WindowFunction;
+use std::sync::mpsc::{
+    self, Receiver, RecvTimeoutError, Sender, SyncSender, TryRecvError, TrySendError,
+};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+// Spectrum rows buffered between the worker and the render loop
+const ROW_QUEUE_LENGTH: usize = 64;
+// Pause before reading again after the source failed
+const ERROR_BACKOFF: Duration = Duration::from_secs(1);
+// Longest wait for the worker to finish its row on stop
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+type Command = Box<dyn FnOnce(&mut DataSupplier) -> Result<()> + Send>;
+
+/// Spectrum row produced by the worker
+pub struct Row {
+    // Spectrum of each channel, detection and levels use the first one
+    pub spectra: Vec<Vec<f32>>,
+    // Phase difference between the first two channels in radians, if computed
+    pub phase_difference: Option<Vec<f32>>,
+    pub stats: RowStats,
+    // Bins belonging to detected signals, empty without detection
+    pub detected: Vec<bool>,
+    // Bins covered by annotations of the source, such as a SigMF recording
+    pub annotated: Vec<bool>,
+}
+
+/// Settings of the data supplier, published for display
+#[derive(Clone, Debug)]
+pub struct Status {
+    pub frequency: f64,
+    pub samplerate: f64,
+    pub ppm: f64,
+    pub sweep: Option<Sweep>,
+    pub fft_size: usize,
+    pub window_function: WindowFunction,
+    pub averaging_mode: AveragingMode,
+    pub averaging: u32,
+    pub gain_status: String,
+    // dBFS, or dBm when calibrated
+    pub power_unit: &'static str,
+    pub dc_mode: DcMode,
+    pub iq_correction: bool,
+    pub recording: bool,
+    pub channels: usize,
+    pub cross_spectrum: bool,
+    pub detection: bool,
+    pub signals: Vec<DetectedSignal>,
+    pub statistics: Statistics,
+    // Rows discarded because rendering could not keep up
+    pub dropped_rows: u64,
+    // Source ran out of samples, no more rows will come
+    pub finished: bool,
+}
+
+impl Status {
+    fn new(supplier: &DataSupplier) -> Self {
+        Self {
+            frequency: supplier.frequency(),
+            samplerate: supplier.samplerate(),
+            ppm: supplier.ppm(),
+            sweep: supplier.sweep(),
+            fft_size: supplier.fft_size(),
+            window_function: supplier.window_function(),
+            averaging_mode: supplier.averaging_mode(),
+            averaging: supplier.averaging(),
+            gain_status: String::from(supplier.gain_status()),
+            power_unit: supplier.power_unit(),
+            dc_mode: supplier.dc_mode(),
+            iq_correction: supplier.iq_correction(),
+            recording: supplier.is_recording(),
+            channels: supplier.num_channels(),
+            cross_spectrum: supplier.cross_spectrum(),
+            detection: supplier.detection(),
+            signals: supplier.signals(),
+            statistics: supplier.statistics(),
+            dropped_rows: 0,
+            finished: false,
+        }
+    }
+}
+
+/// Runs a data supplier on a worker thread so that slow sources don't stall rendering
+pub struct Acquisition {
+    // Changes to apply to the supplier between two rows
+    commands: Option<Sender<Command>>,
+    // Spectrum rows produced by the worker
+    rows: Receiver<Row>,
+    // Latest settings of the supplier
+    status: Arc<Mutex<Status>>,
+    // Worker thread, joined on stop unless stuck in a read
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Acquisition {
+    pub fn start(mut supplier: DataSupplier) -> Result<Self> {
+        let (commands, command_receiver) = mpsc::channel::<Command>();
+        let (row_sender, rows) = mpsc::sync_channel(ROW_QUEUE_LENGTH);
+        let status = Arc::new(Mutex::new(Status::new(&supplier)));
+        let worker_status = status.clone();
+
+        let worker = std::thread::Builder::new()
+            .name(String::from("acquisition"))
+            .spawn(move || Self::run(&mut supplier, command_receiver, row_sender, worker_status))
+            .map_err(io("Cannot start acquisition thread"))?;
+
+        Ok(Self {
+            commands: Some(commands),
+            rows,
+            status,
+            worker: Some(worker),
+        })
+    }
+
+    fn run(
+        supplier: &mut DataSupplier,
+        commands: Receiver<Command>,
+        rows: SyncSender<Row>,
+        status: Arc<Mutex<Status>>,
+    ) {
+        let mut dropped_rows = 0;
+        loop {
+            loop {
+                match commands.try_recv() {
+                    Ok(command) => {
+                        // A rejected setting leaves the previous one in place
+                        if let Err(error) = command(supplier) {
+                            eprintln!("{}", error);
+                        }
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Self::finish(supplier),
+                }
+            }
+
+            match supplier.get_block() {
+                Ok(_) => (),
+                Err(Error::EndOfStream) => {
+                    println!("End of input reached");
+                    status.lock().unwrap().finished = true;
+                    return Self::finish(supplier);
+                }
+                Err(error) => {
+                    eprintln!("{}", error);
+                    std::thread::sleep(ERROR_BACKOFF);
+                    continue;
+                }
+            }
+            let row = Row {
+                spectra: (0..supplier.num_channels())
+                    .map(|channel| supplier.channel_spectrum(channel).to_vec())
+                    .collect(),
+                phase_difference: supplier.phase_difference().map(<[f32]>::to_vec),
+                stats: supplier.row_stats(),
+                detected: supplier.detected_bins().to_vec(),
+                annotated: supplier.annotated_bins().to_vec(),
+            };
+            // When rendering can't keep up, drop rows rather than samples
+            match rows.try_send(row) {
+                Ok(()) => (),
+                Err(TrySendError::Full(_)) => dropped_rows += 1,
+                Err(TrySendError::Disconnected(_)) => return Self::finish(supplier),
+            }
+
+            *status.lock().unwrap() = Status {
+                dropped_rows,
+                ..Status::new(supplier)
+            };
+        }
+    }
+
+    fn finish(supplier: &mut DataSupplier) {
+        if let Err(error) = supplier.stop_recording() {
+            eprintln!("{}", error);
+        }
+    }
+
+    /// Apply a change to the data supplier from the worker thread, errors are reported there
+    pub fn send<F>(&self, command: F)
+    where
+        F: FnOnce(&mut DataSupplier) -> Result<()> + Send + 'static,
+    {
+        if let Some(commands) = &self.commands {
+            // The worker only goes away on stop
+            let _ = commands.send(Box::new(command));
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Rows produced since the last call, waiting up to `timeout` for the first one
+    pub fn rows(&self, timeout: Duration) -> Vec<Row> {
+        let mut rows = match self.rows.recv_timeout(timeout) {
+            Ok(row) => vec![row],
+            Err(RecvTimeoutError::Timeout) => Vec::new(),
+            // The worker is done, keep pacing the caller as if waiting for rows
+            Err(RecvTimeoutError::Disconnected) => {
+                std::thread::sleep(timeout);
+                Vec::new()
+            }
+        };
+        rows.extend(self.rows.try_iter());
+        rows
+    }
+
+    /// Stop the worker, finishing any recording in progress
+    pub fn stop(&mut self) {
+        self.commands = None;
+        if let Some(worker) = self.worker.take() {
+            // A read that never returns, e.g. from a silent standard input, would
+            // block the join forever, leave such a worker behind
+            let deadline = Instant::now() + STOP_TIMEOUT;
+            while !worker.is_finished() && Instant::now() < deadline {
+                std::thread::sleep(STOP_POLL_INTERVAL);
+            }
+            if !worker.is_finished() {
+                eprintln!("Acquisition thread is blocked reading samples, not waiting for it");
+                return;
+            }
+            if worker.join().is_err() {
+                eprintln!("Acquisition thread panicked");
+            }
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::fmt;
+
+/// How the power spectra of successive FFT blocks are combined
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AveragingMode {
+    /// Mean of the blocks of each row
+    Mean,
+    /// Median of the blocks of each row, robust to impulsive interference
+    Median,
+    /// Exponential moving average across blocks and rows
+    Exponential,
+    /// Maximum since the last reset
+    PeakHold,
+    /// Minimum since the last reset
+    MinHold,
+}
+
+impl AveragingMode {
+    /// Next mode in the runtime selection cycle
+    pub fn next(&self) -> Self {
+        match self {
+            AveragingMode::Mean => AveragingMode::Median,
+            AveragingMode::Median => AveragingMode::Exponential,
+            AveragingMode::Exponential => AveragingMode::PeakHold,
+            AveragingMode::PeakHold => AveragingMode::MinHold,
+            AveragingMode::MinHold => AveragingMode::Mean,
+        }
+    }
+}
+
+impl fmt::Display for AveragingMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AveragingMode::Mean => write!(f, "mean"),
+            AveragingMode::Median => write!(f, "median"),
+            AveragingMode::Exponential => write!(f, "exponential"),
+            AveragingMode::PeakHold => write!(f, "peak hold"),
+            AveragingMode::MinHold => write!(f, "min hold"),
+        }
+    }
+}
+
+/// Combines power spectra into spectrum rows according to an `AveragingMode`
+#[derive(Clone)]
+pub struct Averager {
+    mode: AveragingMode,
+    // Weight of the newest block in exponential mode
+    alpha: f32,
+    // Blocks added to the current row
+    count: u32,
+    // Sum of the blocks of the current row, for the mean
+    sum: Vec<f32>,
+    // Blocks of the current row one after the other, for the median, the
+    // allocation is reused from row to row
+    blocks: Vec<f32>,
+    // State kept across rows in exponential and hold modes
+    state: Vec<f32>,
+    // Whether state holds data since the last reset
+    state_valid: bool,
+    // Scratch memory for the median of one bin
+    column: Vec<f32>,
+    // Output row
+    row: Vec<f32>,
+}
+
+impl Averager {
+    pub fn new(mode: AveragingMode, alpha: f32, size: usize) -> Self {
+        Self {
+            mode,
+            alpha: alpha.clamp(0.0, 1.0),
+            count: 0,
+            sum: vec![0.0; size],
+            blocks: Vec::new(),
+            state: vec![0.0; size],
+            state_valid: false,
+            column: Vec::new(),
+            row: vec![0.0; size],
+        }
+    }
+
+    pub fn mode(&self) -> AveragingMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: AveragingMode) {
+        self.mode = mode;
+        self.reset();
+    }
+
+    /// Change the number of bins, discarding any accumulated data
+    pub fn resize(&mut self, size: usize) {
+        self.sum.resize(size, 0.0);
+        self.state.resize(size, 0.0);
+        self.row.resize(size, 0.0);
+        self.reset();
+    }
+
+    /// Restart exponential averaging and peak/min hold from the next block
+    pub fn reset(&mut self) {
+        self.count = 0;
+        self.sum.iter_mut().for_each(|x| *x = 0.0);
+        self.blocks.clear();
+        self.state_valid = false;
+    }
+
+    /// Add the power spectrum of one FFT block
+    pub fn add(&mut self, spectrum: &[f32]) {
+        self.count += 1;
+        match self.mode {
+            AveragingMode::Mean => {
+                for (sum, x) in self.sum.iter_mut().zip(spectrum) {
+                    *sum += x;
+                }
+            }
+            AveragingMode::Median => self.blocks.extend_from_slice(spectrum),
+            _ if !self.state_valid => {
+                self.state.copy_from_slice(spectrum);
+                self.state_valid = true;
+            }
+            AveragingMode::Exponential => {
+                for (state, x) in self.state.iter_mut().zip(spectrum) {
+                    *state += self.alpha * (x - *state);
+                }
+            }
+            AveragingMode::PeakHold => {
+                for (state, x) in self.state.iter_mut().zip(spectrum) {
+                    *state = state.max(*x);
+                }
+            }
+            AveragingMode::MinHold => {
+                for (state, x) in self.state.iter_mut().zip(spectrum) {
+                    *state = state.min(*x);
+                }
+            }
+        }
+    }
+
+    /// Produce the row for the blocks added so far and start a new one
+    pub fn finish(&mut self) -> &[f32] {
+        match self.mode {
+            AveragingMode::Mean => {
+                // Only blocks that were read are counted, skipped ones don't dilute the mean
+                let scale = 1.0 / self.count.max(1) as f32;
+                for (row, sum) in self.row.iter_mut().zip(self.sum.iter_mut()) {
+                    *row = *sum * scale;
+                    *sum = 0.0;
+                }
+            }
+            AveragingMode::Median => {
+                let size = self.row.len();
+                if !self.blocks.is_empty() {
+                    for bin in 0..size {
+                        self.column.clear();
+                        self.column
+                            .extend(self.blocks.chunks_exact(size).map(|block| block[bin]));
+                        let middle = self.column.len() / 2;
+                        let (_, median, _) = self
+                            .column
+                            .select_nth_unstable_by(middle, |a, b| a.total_cmp(b));
+                        self.row[bin] = *median;
+                    }
+                } else {
+                    self.row.iter_mut().for_each(|x| *x = 0.0);
+                }
+                self.blocks.clear();
+            }
+            _ => self.row.copy_from_slice(&self.state),
+        }
+        self.count = 0;
+        &self.row
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn median_ignores_impulsive_blocks() {
+        let mut averager = Averager::new(AveragingMode::Median, 0.1, 3);
+        averager.add(&[1.0, 5.0, 2.0]);
+        averager.add(&[100.0, 4.0, 2.0]);
+        averager.add(&[2.0, 6.0, 100.0]);
+        assert_eq!(averager.finish(), [2.0, 5.0, 2.0]);
+
+        // The next row starts from scratch
+        averager.add(&[7.0, 8.0, 9.0]);
+        assert_eq!(averager.finish(), [7.0, 8.0, 9.0]);
+    }
+}
@@ -0,0 +1,134 @@
+use crate::error::{io, Error, Result};
+use serde::Deserialize;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Measured offsets turning dBFS into dBm, per device and gain
+///
+/// The file is a JSON array of entries such as
+/// `{"device": "rtlsdr", "gain": 29.7, "offset": -12.5}`, where `device` is
+/// matched against the source description and `gain` may be left out for an
+/// offset valid at any gain.
+pub struct Calibration {
+    entries: Vec<CalibrationEntry>,
+}
+
+#[derive(Deserialize)]
+struct CalibrationEntry {
+    // Part of the source description, e.g. a SoapySDR hardware key
+    device: String,
+    // Overall gain in dB the offset was measured at
+    gain: Option<f64>,
+    // Level in dBm of a full scale signal
+    offset: f64,
+}
+
+impl FromStr for Calibration {
+    type Err = serde_json::Error;
+
+    /// Parse the JSON contents of a calibration file
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(Self {
+            entries: serde_json::from_str(s)?,
+        })
+    }
+}
+
+impl Calibration {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path).map_err(io(format!(
+            "Cannot read calibration file {}",
+            path.display()
+        )))?;
+        contents.parse().map_err(|error| {
+            Error::InvalidSetting(format!(
+                "Invalid calibration file {}: {}",
+                path.display(),
+                error
+            ))
+        })
+    }
+
+    /// Offset in dB for `device` at `gain`, interpolated between the measured gains
+    pub fn offset(&self, device: &str, gain: Option<f64>) -> Option<f64> {
+        let entries: Vec<&CalibrationEntry> = self
+            .entries
+            .iter()
+            .filter(|entry| device.contains(&entry.device))
+            .collect();
+        let any_gain = entries.iter().find(|entry| entry.gain.is_none());
+
+        let mut points: Vec<(f64, f64)> = entries
+            .iter()
+            .filter_map(|entry| Some((entry.gain?, entry.offset)))
+            .collect();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let gain = match gain {
+            Some(gain) if !points.is_empty() => gain,
+            // Unknown gain, e.g. AGC, only a gain independent offset applies
+            _ => return any_gain.map(|entry| entry.offset),
+        };
+
+        // Hold the first and last points beyond the measured gains
+        let (first, last) = (points[0], points[points.len() - 1]);
+        if gain <= first.0 {
+            return Some(first.1);
+        }
+        if gain >= last.0 {
+            return Some(last.1);
+        }
+        points.windows(2).find_map(|pair| {
+            let ((g0, o0), (g1, o1)) = (pair[0], pair[1]);
+            (gain <= g1).then(|| o0 + (o1 - o0) * (gain - g0) / (g1 - g0))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = r#"[
+        {"device": "rtlsdr", "gain": 40.0, "offset": -30.0},
+        {"device": "rtlsdr", "gain": 0.0, "offset": -10.0},
+        {"device": "rtlsdr", "gain": 20.0, "offset": -18.0},
+        {"device": "hackrf", "offset": -5.0}
+    ]"#;
+
+    #[test]
+    fn parses_entries() {
+        let calibration: Calibration = FILE.parse().unwrap();
+        assert_eq!(calibration.entries.len(), 4);
+        assert_eq!(calibration.entries[3].device, "hackrf");
+        assert_eq!(calibration.entries[3].gain, None);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        for contents in [
+            "",
+            r#"{"device": "rtlsdr", "offset": -10.0}"#,
+            r#"[{"device": "rtlsdr", "gain": 10.0}]"#,
+            r#"[{"device": "rtlsdr", "offset": "-10"}]"#,
+            r#"[{"device": "rtlsdr", "offset": -10.0}"#,
+        ] {
+            assert!(contents.parse::<Calibration>().is_err(), "{:?}", contents);
+        }
+    }
+
+    #[test]
+    fn interpolates_between_gains_and_clamps_beyond() {
+        let calibration: Calibration = FILE.parse().unwrap();
+        let offset = |gain| calibration.offset("rtlsdr (R820T)", gain);
+        assert_eq!(offset(Some(0.0)), Some(-10.0));
+        assert_eq!(offset(Some(10.0)), Some(-14.0));
+        assert_eq!(offset(Some(30.0)), Some(-24.0));
+        assert_eq!(offset(Some(-5.0)), Some(-10.0));
+        assert_eq!(offset(Some(50.0)), Some(-30.0));
+        // Without a known gain only a gain independent entry applies
+        assert_eq!(offset(None), None);
+        assert_eq!(calibration.offset("hackrf", None), Some(-5.0));
+        assert_eq!(calibration.offset("hackrf", Some(14.0)), Some(-5.0));
+        assert_eq!(calibration.offset("airspy", Some(14.0)), None);
+    }
+}
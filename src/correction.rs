@@ -0,0 +1,207 @@
+use num::complex::Complex32;
+use std::fmt;
+
+// Weight of the newest block in the running DC and IQ imbalance estimates
+const ESTIMATE_ALPHA: f32 = 0.05;
+
+/// How the DC offset of direct conversion receivers is removed
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DcMode {
+    /// Keep the DC spike
+    Off,
+    /// Subtract the running mean of the samples
+    Mean,
+    /// Replace the bins around DC by interpolating their neighbours
+    Interpolate,
+    /// Let the device remove it, if supported
+    Hardware,
+}
+
+impl DcMode {
+    /// Following mode, used to cycle through all of them
+    pub fn next(self) -> Self {
+        match self {
+            DcMode::Off => DcMode::Mean,
+            DcMode::Mean => DcMode::Interpolate,
+            DcMode::Interpolate => DcMode::Hardware,
+            DcMode::Hardware => DcMode::Off,
+        }
+    }
+}
+
+impl fmt::Display for DcMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DcMode::Off => "off",
+            DcMode::Mean => "mean",
+            DcMode::Interpolate => "interpolate",
+            DcMode::Hardware => "hardware",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Running mean subtraction
+pub struct DcBlocker {
+    // Estimated DC offset
+    mean: Complex32,
+}
+
+impl DcBlocker {
+    pub fn new() -> Self {
+        Self {
+            mean: Complex32::new(0.0, 0.0),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.mean = Complex32::new(0.0, 0.0);
+    }
+
+    pub fn apply(&mut self, samples: &mut [Complex32]) {
+        if samples.is_empty() {
+            return;
+        }
+        let block_mean = samples.iter().sum::<Complex32>() / samples.len() as f32;
+        self.mean += (block_mean - self.mean) * ESTIMATE_ALPHA;
+        samples.iter_mut().for_each(|x| *x -= self.mean);
+    }
+}
+
+/// Blind correction of the gain and phase mismatch between the I and Q branches
+pub struct IqCorrector {
+    // Running estimates of E[I²], E[Q²] and E[IQ]
+    ii: f32,
+    qq: f32,
+    iq: f32,
+}
+
+impl IqCorrector {
+    pub fn new() -> Self {
+        Self {
+            ii: 0.0,
+            qq: 0.0,
+            iq: 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn apply(&mut self, samples: &mut [Complex32]) {
+        if samples.is_empty() {
+            return;
+        }
+        let n = samples.len() as f32;
+        let ii = samples.iter().map(|x| x.re * x.re).sum::<f32>() / n;
+        let qq = samples.iter().map(|x| x.im * x.im).sum::<f32>() / n;
+        let iq = samples.iter().map(|x| x.re * x.im).sum::<f32>() / n;
+        if self.ii == 0.0 {
+            (self.ii, self.qq, self.iq) = (ii, qq, iq);
+        } else {
+            self.ii += (ii - self.ii) * ESTIMATE_ALPHA;
+            self.qq += (qq - self.qq) * ESTIMATE_ALPHA;
+            self.iq += (iq - self.iq) * ESTIMATE_ALPHA;
+        }
+        if self.ii <= 0.0 || self.qq <= 0.0 {
+            return;
+        }
+
+        // With Q = g·(sin θ·cos φ + cos θ·sin φ), recover sin θ from Q and I
+        let gain = (self.qq / self.ii).sqrt();
+        let sin_phi = (self.iq / (self.ii * self.qq).sqrt()).clamp(-0.99, 0.99);
+        let cos_phi = (1.0 - sin_phi * sin_phi).sqrt();
+        for x in samples.iter_mut() {
+            x.im = (x.im / gain - x.re * sin_phi) / cos_phi;
+        }
+    }
+}
+
+/// Replace `half_width` bins on each side of `center` by a line between their neighbours
+pub fn interpolate_bins(spectrum: &mut [f32], center: usize, half_width: usize) {
+    if center < half_width + 1 || center + half_width + 1 >= spectrum.len() {
+        return;
+    }
+    let first = center - half_width - 1;
+    let last = center + half_width + 1;
+    let (left, right) = (spectrum[first], spectrum[last]);
+    let span = (last - first) as f32;
+    for (i, bin) in spectrum[first + 1..last].iter_mut().enumerate() {
+        let t = (i + 1) as f32 / span;
+        *bin = left + (right - left) * t;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::TAU;
+
+    const BLOCK_SIZE: usize = 1024;
+    // Tone frequency in cycles per block, right on a bin
+    const CYCLES: f32 = 50.0;
+
+    // Block `index` of a unit tone received with a DC offset and the Q branch
+    // `gain` times too strong and `phase` radians off quadrature
+    fn impaired_block(index: usize, dc: Complex32, gain: f32, phase: f32) -> Vec<Complex32> {
+        (0..BLOCK_SIZE)
+            .map(|i| {
+                let angle = TAU * CYCLES * (index * BLOCK_SIZE + i) as f32 / BLOCK_SIZE as f32;
+                Complex32::new(angle.cos(), gain * (angle + phase).sin()) + dc
+            })
+            .collect()
+    }
+
+    // Power at `cycles` per block, a single DFT bin
+    fn bin_power(samples: &[Complex32], cycles: f32) -> f32 {
+        let n = samples.len() as f32;
+        samples
+            .iter()
+            .enumerate()
+            .map(|(i, x)| x * Complex32::from_polar(1.0, -TAU * cycles * i as f32 / n))
+            .sum::<Complex32>()
+            .norm_sqr()
+    }
+
+    fn image_rejection_db(samples: &[Complex32]) -> f32 {
+        10.0 * (bin_power(samples, CYCLES) / bin_power(samples, -CYCLES)).log10()
+    }
+
+    #[test]
+    fn dc_blocker_removes_the_offset() {
+        let dc = Complex32::new(0.3, -0.2);
+        let mut blocker = DcBlocker::new();
+        let mut block = Vec::new();
+        for index in 0..200 {
+            block = impaired_block(index, dc, 1.0, 0.0);
+            blocker.apply(&mut block);
+        }
+        let mean = block.iter().sum::<Complex32>() / BLOCK_SIZE as f32;
+        assert!(mean.norm() < 1e-3, "{}", mean);
+        blocker.reset();
+        assert_eq!(blocker.mean, Complex32::new(0.0, 0.0));
+    }
+
+    #[test]
+    fn iq_corrector_suppresses_the_image() {
+        let (gain, phase) = (1.2, 10f32.to_radians());
+        let before = image_rejection_db(&impaired_block(0, Complex32::default(), gain, phase));
+        let mut corrector = IqCorrector::new();
+        let mut block = Vec::new();
+        for index in 0..20 {
+            block = impaired_block(index, Complex32::default(), gain, phase);
+            corrector.apply(&mut block);
+        }
+        let after = image_rejection_db(&block);
+        assert!(before < 20.0, "{} dB before correction", before);
+        assert!(after - before > 30.0, "{} dB -> {} dB", before, after);
+    }
+
+    #[test]
+    fn interpolates_over_the_dc_bins() {
+        let mut spectrum = [1.0, 2.0, 9.0, 9.0, 9.0, 6.0, 7.0];
+        interpolate_bins(&mut spectrum, 3, 1);
+        assert_eq!(spectrum, [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0]);
+    }
+}
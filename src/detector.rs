@@ -0,0 +1,249 @@
+use chrono::{DateTime, Duration, Utc};
+
+// Bins next to the tested one left out of the noise estimate, so signal skirts don't raise it
+const GUARD_CELLS: usize = 4;
+// Bins on each side of the guard cells averaged into the noise estimate
+const TRAINING_CELLS: usize = 32;
+// Bins below the threshold bridged when grouping, keeps a signal with a dip in one piece
+const MERGE_GAP: usize = 2;
+// Rows a signal has to show up in before it is reported, filters noise spikes
+const MIN_DETECTIONS: u32 = 3;
+// Signals not seen for this long are forgotten
+const SIGNAL_TIMEOUT_SECONDS: i64 = 10;
+
+/// Signal found by the detector, tracked across rows
+#[derive(Clone, Debug, PartialEq)]
+pub struct DetectedSignal {
+    // Centre frequency in Hz, from the latest detection
+    pub frequency: f64,
+    // Occupied bandwidth in Hz, from the latest detection
+    pub bandwidth: f64,
+    // Strongest bin seen in any detection, in dBFS or dBm when calibrated
+    pub peak_power: f32,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    // Rows the signal was detected in
+    pub detections: u32,
+}
+
+impl DetectedSignal {
+    fn low(&self) -> f64 {
+        self.frequency - self.bandwidth / 2.0
+    }
+
+    fn high(&self) -> f64 {
+        self.frequency + self.bandwidth / 2.0
+    }
+}
+
+/// Cell-averaging CFAR detector, grouping bins above the local noise into signals
+pub struct Detector {
+    // Power above the local noise estimate for a bin to count as signal, in linear scale
+    threshold: f32,
+    // Running sums of the row for the noise estimates
+    prefix_sum: Vec<f64>,
+    // Bins of the last row above the threshold, after bridging small gaps
+    detected: Vec<bool>,
+    // Signals seen recently, ordered by frequency
+    signals: Vec<DetectedSignal>,
+}
+
+impl Detector {
+    pub fn new(threshold_db: f32) -> Self {
+        Self {
+            threshold: 10f32.powf(threshold_db / 10.0),
+            prefix_sum: Vec::new(),
+            detected: Vec::new(),
+            signals: Vec::new(),
+        }
+    }
+
+    /// Detect signals in a row of linear power values, bin `i` being centred on
+    /// `first_frequency + i * bin_width`
+    pub fn process(&mut self, spectrum: &[f32], first_frequency: f64, bin_width: f64) {
+        self.process_at(spectrum, first_frequency, bin_width, Utc::now());
+    }
+
+    // Detect signals in a row received at `now`
+    fn process_at(
+        &mut self,
+        spectrum: &[f32],
+        first_frequency: f64,
+        bin_width: f64,
+        now: DateTime<Utc>,
+    ) {
+        let n = spectrum.len();
+        self.prefix_sum.clear();
+        self.prefix_sum.push(0.0);
+        let mut sum = 0.0;
+        for &x in spectrum {
+            sum += x as f64;
+            self.prefix_sum.push(sum);
+        }
+        let window_sum = |start: usize, end: usize| self.prefix_sum[end] - self.prefix_sum[start];
+
+        self.detected.clear();
+        self.detected.extend((0..n).map(|i| {
+            // Training cells on both sides, or only one near the edges of the row
+            let left_end = i.saturating_sub(GUARD_CELLS);
+            let left_start = left_end.saturating_sub(TRAINING_CELLS);
+            let right_start = (i + GUARD_CELLS + 1).min(n);
+            let right_end = (right_start + TRAINING_CELLS).min(n);
+            let cells = (left_end - left_start) + (right_end - right_start);
+            if cells == 0 {
+                return false;
+            }
+            let noise = (window_sum(left_start, left_end) + window_sum(right_start, right_end))
+                / cells as f64;
+            spectrum[i] as f64 > noise * self.threshold as f64
+        }));
+
+        // Group neighbouring bins into one detection per signal
+        let mut bin = 0;
+        while bin < n {
+            if !self.detected[bin] {
+                bin += 1;
+                continue;
+            }
+            let first = bin;
+            let mut last = bin;
+            while bin < n && bin <= last + MERGE_GAP + 1 {
+                if self.detected[bin] {
+                    last = bin;
+                }
+                bin += 1;
+            }
+            self.detected[first..=last].fill(true);
+
+            let peak_power = spectrum[first..=last]
+                .iter()
+                .copied()
+                .fold(f32::MIN, f32::max);
+            let low = first_frequency + (first as f64 - 0.5) * bin_width;
+            let high = first_frequency + (last as f64 + 0.5) * bin_width;
+            self.update(low, high, 10.0 * peak_power.log10(), now);
+        }
+
+        let timeout = Duration::seconds(SIGNAL_TIMEOUT_SECONDS);
+        self.signals
+            .retain(|signal| now - signal.last_seen < timeout);
+        self.signals
+            .sort_by(|a, b| a.frequency.total_cmp(&b.frequency));
+    }
+
+    // Add a detection spanning `low` to `high` Hz to the signal it overlaps, or start a new one
+    fn update(&mut self, low: f64, high: f64, peak_power: f32, now: DateTime<Utc>) {
+        let existing = self
+            .signals
+            .iter_mut()
+            .find(|signal| signal.low() < high && low < signal.high());
+        match existing {
+            Some(signal) => {
+                // Several detections of the same row overlapping one signal widen it
+                let (low, high) = if signal.last_seen == now {
+                    (low.min(signal.low()), high.max(signal.high()))
+                } else {
+                    signal.detections += 1;
+                    (low, high)
+                };
+                signal.frequency = (low + high) / 2.0;
+                signal.bandwidth = high - low;
+                signal.peak_power = signal.peak_power.max(peak_power);
+                signal.last_seen = now;
+            }
+            None => self.signals.push(DetectedSignal {
+                frequency: (low + high) / 2.0,
+                bandwidth: high - low,
+                peak_power,
+                first_seen: now,
+                last_seen: now,
+                detections: 1,
+            }),
+        }
+    }
+
+    /// Bins of the last row belonging to a signal
+    pub fn detected(&self) -> &[bool] {
+        &self.detected
+    }
+
+    /// Signals seen in enough rows, ordered by frequency
+    pub fn signals(&self) -> Vec<DetectedSignal> {
+        self.signals
+            .iter()
+            .filter(|signal| signal.detections >= MIN_DETECTIONS)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    // Noise floor varying by less than 5 dB around 0 dB
+    fn noise(rng: &mut StdRng) -> Vec<f32> {
+        (0..1024).map(|_| rng.gen_range(0.5..1.5)).collect()
+    }
+
+    fn with_tone(mut spectrum: Vec<f32>) -> Vec<f32> {
+        spectrum[500..=503].fill(1000.0);
+        spectrum
+    }
+
+    fn seconds(t: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + t, 0).unwrap()
+    }
+
+    #[test]
+    fn detects_a_tone_over_its_bins() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut detector = Detector::new(10.0);
+        for t in 0..MIN_DETECTIONS as i64 {
+            assert!(detector.signals().is_empty());
+            detector.process_at(&with_tone(noise(&mut rng)), 100e6, 1e3, seconds(t));
+        }
+        let detected: Vec<usize> = (0..1024).filter(|&i| detector.detected()[i]).collect();
+        assert_eq!(detected, (500..=503).collect::<Vec<_>>());
+        let signals = detector.signals();
+        assert_eq!(signals.len(), 1);
+        assert_eq!(signals[0].frequency, 100.5015e6);
+        assert_eq!(signals[0].bandwidth, 4e3);
+        assert_eq!(signals[0].peak_power, 30.0);
+        assert_eq!(signals[0].detections, MIN_DETECTIONS);
+    }
+
+    #[test]
+    fn ignores_noise_alone() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut detector = Detector::new(10.0);
+        for t in 0..10 {
+            detector.process_at(&noise(&mut rng), 100e6, 1e3, seconds(t));
+            assert!(!detector.detected().contains(&true));
+        }
+        assert!(detector.signals.is_empty());
+    }
+
+    #[test]
+    fn keeps_a_signal_until_it_times_out() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut detector = Detector::new(10.0);
+        for t in 0..MIN_DETECTIONS as i64 {
+            detector.process_at(&with_tone(noise(&mut rng)), 100e6, 1e3, seconds(t));
+        }
+        let last_seen = MIN_DETECTIONS as i64 - 1;
+        detector.process_at(&noise(&mut rng), 100e6, 1e3, seconds(last_seen + 5));
+        let signals = detector.signals();
+        assert_eq!(signals.len(), 1);
+        assert_eq!(signals[0].last_seen, seconds(last_seen));
+        detector.process_at(
+            &noise(&mut rng),
+            100e6,
+            1e3,
+            seconds(last_seen + SIGNAL_TIMEOUT_SECONDS),
+        );
+        assert!(detector.signals().is_empty());
+    }
+}
@@ -0,0 +1,65 @@
+use std::fmt;
+
+/// Errors reported by the sample sources, the data supplier and the plot
+#[derive(Debug)]
+pub enum Error {
+    // SoapySDR device or stream failure, with what was being done
+    Soapy(String, soapysdr::Error),
+    // File or network failure, with what was being done
+    Io(String, std::io::Error),
+    // Malformed SigMF metadata
+    Metadata(serde_json::Error),
+    // Unexpected data from a source, e.g. a server speaking another protocol
+    Protocol(String),
+    // Setting the source cannot apply
+    Unsupported(String),
+    // Setting outside of the valid range
+    InvalidSetting(String),
+    // Source has no more samples and won't be repeated
+    EndOfStream,
+    // OpenGL object creation, shader compilation or program linking failure
+    Gl(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Soapy(context, error) => write!(f, "{}: {}", context, error),
+            Error::Io(context, error) => write!(f, "{}: {}", context, error),
+            Error::Metadata(error) => write!(f, "Invalid SigMF metadata: {}", error),
+            Error::Protocol(message) => f.write_str(message),
+            Error::Unsupported(message) => f.write_str(message),
+            Error::InvalidSetting(message) => f.write_str(message),
+            Error::EndOfStream => f.write_str("End of input reached"),
+            Error::Gl(message) => write!(f, "OpenGL error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Soapy(_, error) => Some(error),
+            Error::Io(_, error) => Some(error),
+            Error::Metadata(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Metadata(error)
+    }
+}
+
+// Attach what was being done to SoapySDR and I/O failures
+pub fn soapy(context: impl Into<String>) -> impl FnOnce(soapysdr::Error) -> Error {
+    move |error| Error::Soapy(context.into(), error)
+}
+
+pub fn io(context: impl Into<String>) -> impl FnOnce(std::io::Error) -> Error {
+    move |error| Error::Io(context.into(), error)
+}
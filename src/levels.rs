@@ -0,0 +1,117 @@
+// Percentile of the bins taken as noise floor, most bins of a spectrum are noise
+const FLOOR_PERCENTILE: f32 = 0.25;
+// Percentile of the bins taken as peak level, ignoring a few outliers
+const PEAK_PERCENTILE: f32 = 0.998;
+// Weight of the newest row in the smoothed levels
+const SMOOTHING: f32 = 0.1;
+// Room left below the noise floor and above the peaks, in dB
+const FLOOR_MARGIN: f32 = 3.0;
+const PEAK_MARGIN: f32 = 6.0;
+// Smallest displayed range, keeps the noise from filling the colormap
+const MIN_RANGE: f32 = 20.0;
+
+/// Tracks the noise floor and peak level of recent spectrum rows
+pub struct LevelEstimator {
+    // Smoothed noise floor and peak level in dB
+    levels: Option<(f32, f32)>,
+    // Power of each bin in dB, reused between rows
+    db: Vec<f32>,
+}
+
+impl LevelEstimator {
+    pub fn new() -> Self {
+        Self {
+            levels: None,
+            db: Vec::new(),
+        }
+    }
+
+    /// Update the estimate with a spectrum row of linear power values
+    pub fn add(&mut self, spectrum: &[f32]) {
+        self.db.clear();
+        self.db.extend(
+            spectrum
+                .iter()
+                .map(|x| 10.0 * x.log10())
+                .filter(|x| x.is_finite()),
+        );
+        if self.db.is_empty() {
+            return;
+        }
+
+        let floor = self.percentile(FLOOR_PERCENTILE);
+        let peak = self.percentile(PEAK_PERCENTILE);
+        self.levels = Some(match self.levels {
+            Some((f, p)) => (f + (floor - f) * SMOOTHING, p + (peak - p) * SMOOTHING),
+            None => (floor, peak),
+        });
+    }
+
+    fn percentile(&mut self, fraction: f32) -> f32 {
+        let index = ((self.db.len() - 1) as f32 * fraction).round() as usize;
+        *self
+            .db
+            .select_nth_unstable_by(index, |a, b| a.total_cmp(b))
+            .1
+    }
+
+    /// Power range in dB to map onto the colormap, once a row was seen
+    pub fn range(&self) -> Option<(f32, f32)> {
+        self.levels.map(|(floor, peak)| {
+            let low = floor - FLOOR_MARGIN;
+            (low, (peak + PEAK_MARGIN).max(low + MIN_RANGE))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Flat floor at `floor_db` with `strong` bins at `peak_db` spread across it
+    fn spectrum(floor_db: f32, peak_db: f32, strong: usize) -> Vec<f32> {
+        let mut spectrum = vec![10f32.powf(floor_db / 10.0); 1024];
+        for i in 0..strong {
+            spectrum[i * 97 % 1024] = 10f32.powf(peak_db / 10.0);
+        }
+        spectrum
+    }
+
+    fn assert_range(estimator: &LevelEstimator, expected: (f32, f32)) {
+        let (low, high) = estimator.range().unwrap();
+        assert!(
+            (low - expected.0).abs() < 0.01 && (high - expected.1).abs() < 0.01,
+            "{:?} instead of {:?}",
+            (low, high),
+            expected
+        );
+    }
+
+    #[test]
+    fn spans_the_floor_to_the_strong_bins() {
+        let mut estimator = LevelEstimator::new();
+        assert_eq!(estimator.range(), None);
+        estimator.add(&spectrum(-80.0, -20.0, 10));
+        assert_range(&estimator, (-80.0 - FLOOR_MARGIN, -20.0 + PEAK_MARGIN));
+    }
+
+    #[test]
+    fn ignores_a_single_outlier() {
+        let mut estimator = LevelEstimator::new();
+        estimator.add(&spectrum(-80.0, 0.0, 1));
+        assert_range(
+            &estimator,
+            (-80.0 - FLOOR_MARGIN, -80.0 - FLOOR_MARGIN + MIN_RANGE),
+        );
+    }
+
+    #[test]
+    fn smooths_level_changes_and_skips_empty_rows() {
+        let mut estimator = LevelEstimator::new();
+        estimator.add(&spectrum(-80.0, -20.0, 10));
+        estimator.add(&vec![0.0; 1024]);
+        assert_range(&estimator, (-80.0 - FLOOR_MARGIN, -20.0 + PEAK_MARGIN));
+        estimator.add(&spectrum(-60.0, -20.0, 10));
+        assert_range(&estimator, (-78.0 - FLOOR_MARGIN, -20.0 + PEAK_MARGIN));
+    }
+}
@@ -1,22 +1,56 @@
+mod acquisition;
+mod averaging;
+mod calibration;
+mod correction;
+mod detector;
+mod error;
+mod levels;
 mod plot;
+mod recorder;
 mod source;
+mod sweep;
+mod units;
+mod window;
 
-use crate::plot::WaterfallPlot;
-use crate::source::DataSupplier;
-use clap::Parser;
+use crate::acquisition::{Acquisition, Status};
+use crate::averaging::AveragingMode;
+use crate::calibration::Calibration;
+use crate::correction::DcMode;
+use crate::detector::DetectedSignal;
+use crate::error::Error;
+use crate::levels::LevelEstimator;
+use crate::plot::{Layout, PlotKind, WaterfallPlot};
+use crate::recorder::Recorder;
+use crate::source::sigmf::is_sigmf_path;
+use crate::source::{
+    demo_scenario, list_devices, DataSupplier, GeneratorSource, LoOffset, RawSource, RtlTcpSource,
+    SampleFormat, SampleSource, SigmfSource, Signal, SoapySource, MAX_FFT_SIZE, MIN_FFT_SIZE,
+};
+use crate::sweep::Sweep;
+use crate::units::parse_frequency;
+use crate::window::{WindowFunction, WindowScaling};
+use clap::{CommandFactory, Parser};
+use glow::HasContext;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
 
 /// Simple program to plot a waterfall from standard input
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    #[arg(short, long)]
-    frequency: u32,
+    /// Center frequency in Hz, with an optional k, M or G suffix (e.g. 433.92M),
+    /// taken from the recording metadata if not given
+    #[arg(short, long, value_parser = parse_frequency)]
+    frequency: Option<f64>,
 
-    #[arg(short, long)]
-    samplerate: u32,
+    /// Sample rate, e.g. 2.4M, the recording metadata takes precedence if it has one
+    #[arg(short, long, value_parser = parse_frequency)]
+    samplerate: Option<f64>,
 
-    #[arg(short, long)]
-    bandwidth: u32,
+    /// Analog bandwidth, e.g. 1.5M, defaults to the sample rate
+    #[arg(short, long, value_parser = parse_frequency)]
+    bandwidth: Option<f64>,
 
     /// Enable vertical synchronization (avoids tearing)
     #[arg(short, long)]
@@ -25,12 +59,313 @@ struct Args {
     /// Number of FFT blocks to average
     #[arg(short, long, default_value_t = 1)]
     averaging: u32,
+
+    /// How FFT blocks are combined into rows
+    #[arg(short, long, value_enum, default_value_t = AveragingMode::Mean)]
+    mode: AveragingMode,
+
+    /// Weight of the newest block in exponential averaging
+    #[arg(long, default_value_t = 0.1)]
+    alpha: f32,
+
+    /// Number of FFT bins, a power of two from 256 to 65536
+    #[arg(long, default_value_t = 2048, value_parser = parse_fft_size)]
+    fft_size: usize,
+
+    /// Percentage of each FFT block shared with the previous one (Welch overlap)
+    #[arg(short, long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=95))]
+    overlap: u8,
+
+    /// FFT window: rectangular, hann, hamming, blackman-harris, flat-top or kaiser[:BETA]
+    #[arg(short, long, default_value = "hann")]
+    window: WindowFunction,
+
+    /// Window power correction
+    #[arg(long, value_enum, default_value_t = WindowScaling::Amplitude)]
+    window_scaling: WindowScaling,
+
+    /// Play back a raw IQ capture ("-" for standard input) or a SigMF recording
+    /// instead of using a SoapySDR device
+    #[arg(short, long)]
+    input: Option<PathBuf>,
+
+    /// Sample format of a raw input file or standard input
+    #[arg(long, value_enum, default_value_t = SampleFormat::Cf32)]
+    format: SampleFormat,
+
+    /// Deliver file or generated samples as fast as possible instead of at the sample rate
+    #[arg(long)]
+    no_throttle: bool,
+
+    /// Start over when reaching the end of the input file
+    #[arg(long)]
+    repeat: bool,
+
+    /// Use the synthetic signal generator, with a demo scenario unless --signal is given
+    #[arg(short, long)]
+    generate: bool,
+
+    /// Generated signal, e.g. noise:-60, tone:100e3:-20, am:<freq>:<power>:<mod_freq>:<depth>,
+    /// fm:<freq>:<power>:<mod_freq>:<deviation>, chirp:<start>:<stop>:<period>:<power>,
+    /// hop:<span>:<channels>:<dwell>:<power>
+    #[arg(long = "signal", value_name = "SPEC")]
+    signals: Vec<Signal>,
+
+    /// Seed of the signal generator
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// SoapySDR device arguments, e.g. "driver=rtlsdr" or "driver=hackrf,serial=..."
+    #[arg(short, long, default_value = "")]
+    device: String,
+
+    /// Receive channels of the SoapySDR device, several ones (e.g. 0,1) are
+    /// received coherently and shown side by side
+    #[arg(
+        short = 'c',
+        long = "channel",
+        value_delimiter = ',',
+        default_value = "0"
+    )]
+    channels: Vec<usize>,
+
+    /// Arrangement of the waterfalls when receiving several channels
+    #[arg(long, value_enum, default_value_t = Layout::SideBySide)]
+    layout: Layout,
+
+    /// Show the phase difference between the first two channels next to their waterfalls
+    #[arg(long)]
+    phase_difference: bool,
+
+    /// List the available SoapySDR devices and exit
+    #[arg(short, long)]
+    list_devices: bool,
+
+    /// Receive from a remote rtl_tcp server at HOST:PORT
+    #[arg(long, value_name = "HOST:PORT")]
+    rtl_tcp: Option<String>,
+
+    /// Overall receiver gain in dB, the source default is used if not given
+    #[arg(long, conflicts_with = "agc")]
+    gain: Option<f64>,
+
+    /// Gain of an individual element in dB, e.g. LNA=16 (repeatable)
+    #[arg(long, value_name = "NAME=GAIN", value_parser = parse_gain_element)]
+    gain_element: Vec<(String, f64)>,
+
+    /// Enable automatic gain control of the device
+    #[arg(long)]
+    agc: bool,
+
+    /// Reference oscillator correction in parts per million
+    #[arg(long)]
+    ppm: Option<f64>,
+
+    /// Frequency of a known carrier (e.g. a beacon or broadcast pilot) used to
+    /// estimate the ppm correction with the Q key
+    #[arg(long, value_name = "FREQUENCY", value_parser = parse_frequency)]
+    reference: Option<f64>,
+
+    /// Record received samples to a SigMF recording with this base name
+    #[arg(short, long)]
+    record: Option<PathBuf>,
+
+    /// Follow the noise floor and peak levels with the colormap range
+    #[arg(long)]
+    auto_levels: bool,
+
+    /// DC spike removal
+    #[arg(long, value_enum, default_value_t = DcMode::Off)]
+    dc_removal: DcMode,
+
+    /// Estimate and correct the IQ gain and phase imbalance of the receiver
+    #[arg(long)]
+    iq_correction: bool,
+
+    /// JSON file of per-device and per-gain offsets showing levels in dBm instead of dBFS
+    #[arg(long, value_name = "FILE")]
+    calibration: Option<PathBuf>,
+
+    /// Detect signals standing out of the local noise and list them
+    #[arg(long)]
+    detect: bool,
+
+    /// How far above the local noise a bin must be to belong to a signal, in dB
+    #[arg(long, default_value_t = 10.0)]
+    detection_threshold: f32,
+
+    /// Tune the LO this far from the center frequency (e.g. 250k, 0 or "auto" for half the
+    /// bandwidth) to keep the DC spike out of the way, the spectrum stays centered
+    #[arg(long, value_name = "OFFSET")]
+    lo_offset: Option<LoOffset>,
+
+    /// Sweep the tuner from START to STOP (e.g. 400M:520M), stitching the spectra into wide rows
+    #[arg(long, value_name = "START:STOP")]
+    sweep: Option<Sweep>,
+
+    /// Fraction of each FFT kept when sweeping, the edges suffer from filter roll-off
+    #[arg(long, default_value_t = 0.75)]
+    sweep_keep: f64,
+
+    /// Time discarded after each sweep step while the tuner settles, in milliseconds
+    #[arg(long, default_value_t = 2.0)]
+    settle_time: f64,
+}
+
+// Value of a setting that may be omitted only if the source provides it
+fn required_arg(value: Option<f64>, default: Option<f64>, name: &str) -> f64 {
+    value.or(default).unwrap_or_else(|| {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                format!("--{} is required for this source", name),
+            )
+            .exit()
+    })
+}
+
+fn parse_fft_size(s: &str) -> Result<usize, String> {
+    let size: usize = s.parse().map_err(|_| format!("invalid size {:?}", s))?;
+    if size.is_power_of_two() && (MIN_FFT_SIZE..=MAX_FFT_SIZE).contains(&size) {
+        Ok(size)
+    } else {
+        Err(format!(
+            "must be a power of two between {} and {}",
+            MIN_FFT_SIZE, MAX_FFT_SIZE
+        ))
+    }
+}
+
+fn parse_gain_element(s: &str) -> Result<(String, f64), String> {
+    let (name, gain) = s
+        .split_once('=')
+        .ok_or_else(|| String::from("expected NAME=GAIN"))?;
+    let gain = gain
+        .parse()
+        .map_err(|_| format!("invalid gain {:?}", gain))?;
+    Ok((String::from(name), gain))
+}
+
+fn window_title(status: &Status, gain_stage: &str) -> String {
+    let tuning = match status.sweep {
+        Some(sweep) => format!("sweep {}", sweep),
+        None => format!("{:.3} MHz", status.frequency / 1e6),
+    };
+    let mut title = format!(
+        "Waterfall - {}, {:.3} MS/s, {} bins {}, {} x{}",
+        tuning,
+        status.samplerate / 1e6,
+        status.fft_size,
+        status.window_function,
+        status.averaging_mode,
+        status.averaging,
+    );
+    if status.ppm != 0.0 {
+        title += &format!(" ({:+.2} ppm)", status.ppm);
+    }
+    if status.channels > 1 {
+        title += &format!(" - {} channels", status.channels);
+        if status.cross_spectrum {
+            title += " with phase difference";
+        }
+    }
+    if !status.gain_status.is_empty() {
+        title += &format!(" - Gain {} - adjusting {}", status.gain_status, gain_stage);
+    }
+    let statistics = &status.statistics;
+    if statistics.gap_rows > 0 || status.dropped_rows > 0 {
+        title += &format!(
+            " - {} overflows, {} blocks and {} rows dropped",
+            statistics.overflows, statistics.dropped_blocks, status.dropped_rows
+        );
+    }
+    if status.dc_mode != DcMode::Off {
+        title += &format!(" - DC {}", status.dc_mode);
+    }
+    if status.iq_correction {
+        title += " - IQ corrected";
+    }
+    if status.power_unit != "dBFS" {
+        title += &format!(" - {}", status.power_unit);
+    }
+    if status.detection {
+        title += &format!(" - {} signals", status.signals.len());
+    }
+    if status.recording {
+        title += " [REC]";
+    }
+    if status.finished {
+        title += " - end of input";
+    }
+    title
+}
+
+// Share the window between the plots, the first `num_channels` ones showing
+// the channels side by side or blended together
+unsafe fn arrange_plots(
+    plots: &mut [WaterfallPlot],
+    num_channels: usize,
+    layout: Layout,
+    width: u32,
+    height: u32,
+) {
+    let overlay = layout == Layout::Overlay;
+    let columns = if overlay {
+        plots.len() - num_channels + 1
+    } else {
+        plots.len()
+    };
+    let column_width = width / columns as u32;
+    for (i, plot) in plots.iter_mut().enumerate() {
+        let (column, opacity) = match i < num_channels && overlay {
+            // Each channel weighs as much as the ones below it together
+            true => (0, 1.0 / (i + 1) as f32),
+            false if overlay => (i - num_channels + 1, 1.0),
+            false => (i, 1.0),
+        };
+        plot.set_viewport((column as u32 * column_width) as i32, column_width, height);
+        plot.set_opacity(opacity);
+    }
+}
+
+// List the detected signals on the console
+fn print_signals(signals: &[DetectedSignal], unit: &str) {
+    println!("{} signals detected", signals.len());
+    for signal in signals {
+        println!(
+            "  {:>12.6} MHz  {:>9.3} kHz  {:>6.1} {}  first seen {}  last seen {}",
+            signal.frequency / 1e6,
+            signal.bandwidth / 1e3,
+            signal.peak_power,
+            unit,
+            signal.first_seen.format("%H:%M:%S"),
+            signal.last_seen.format("%H:%M:%S"),
+        );
+    }
+}
+
+// Print an error that is not fatal, the previous setting stays in effect
+fn report(result: error::Result<()>) {
+    if let Err(error) = result {
+        eprintln!("{}", error);
+    }
 }
 
 fn main() {
     // Command line argument parsing
     let args = Args::parse();
 
+    if let Err(error) = run(args) {
+        eprintln!("Error: {}", error);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Args) -> error::Result<()> {
+    if args.list_devices {
+        return list_devices();
+    }
+
     // Window creation
     let event_loop = glutin::event_loop::EventLoop::new();
     let wb = glutin::window::WindowBuilder::new()
@@ -43,21 +378,136 @@ fn main() {
         })
         .with_vsync(args.vsync)
         .build_windowed(wb, &event_loop)
-        .unwrap();
-    let window = unsafe { windowed_context.make_current().unwrap() };
+        .map_err(|error| Error::Gl(error.to_string()))?;
+    let window = unsafe {
+        windowed_context
+            .make_current()
+            .map_err(|(_, error)| Error::Gl(error.to_string()))?
+    };
     let gl =
         unsafe { glow::Context::from_loader_function(|s| window.get_proc_address(s) as *const _) };
+    let gl = Rc::new(gl);
 
-    //
-    let mut waterfallplot = unsafe { WaterfallPlot::new(gl) };
-    let mut samples_supplier = DataSupplier::new(args.averaging);
+    let synthetic = args.input.is_none() && (args.generate || !args.signals.is_empty());
+    let source: Box<dyn SampleSource> = match &args.input {
+        None if synthetic => {
+            let signals = if args.signals.is_empty() {
+                demo_scenario()
+            } else {
+                args.signals.clone()
+            };
+            Box::new(GeneratorSource::new(signals, args.seed, !args.no_throttle))
+        }
+        Some(path) if path == Path::new("-") => {
+            Box::new(RawSource::stdin(args.format, !args.no_throttle))
+        }
+        Some(path) if is_sigmf_path(path) => {
+            Box::new(SigmfSource::open(path, !args.no_throttle, args.repeat)?)
+        }
+        Some(path) => Box::new(RawSource::open(
+            path,
+            args.format,
+            !args.no_throttle,
+            args.repeat,
+        )?),
+        None => match &args.rtl_tcp {
+            Some(address) => Box::new(RtlTcpSource::connect(address)?),
+            None => Box::new(SoapySource::new(&args.device, &args.channels)?),
+        },
+    };
+    let sweep = args.sweep.map(|sweep| Sweep {
+        keep: args.sweep_keep.clamp(0.1, 1.0),
+        settle_time: args.settle_time.max(0.0) / 1e3,
+        ..sweep
+    });
+    // Synthetic signals are placed around 0 Hz unless told otherwise
+    let default_frequency = source
+        .frequency()
+        .or(sweep.map(|s| (s.start + s.stop) / 2.0))
+        .or(synthetic.then_some(0.0));
+    let frequency = required_arg(args.frequency, default_frequency, "frequency");
+    let samplerate = required_arg(args.samplerate, source.samplerate(), "samplerate");
+    let bandwidth = args.bandwidth.unwrap_or(samplerate);
+    let mut samples_supplier = DataSupplier::new(
+        source,
+        args.averaging,
+        args.mode,
+        args.alpha,
+        args.fft_size,
+        args.window,
+        args.window_scaling,
+    )?;
     let mut last_touch: f64 = 0.0;
-    let mut frequency = args.frequency;
+    let mut title = String::new();
+    let gain_stages = samples_supplier.gain_stages();
+    let mut gain_stage = 0;
+    let mut levels = LevelEstimator::new();
+    let mut auto_levels = args.auto_levels;
+
+    report(samples_supplier.set_overlap(args.overlap as f32 / 100.0));
+    report(samples_supplier.set_samplerate(samplerate));
+    report(samples_supplier.set_bandwidth(bandwidth));
+    if let Some(offset) = args.lo_offset {
+        report(samples_supplier.set_lo_offset(offset));
+    }
+    report(samples_supplier.set_frequency(frequency));
+    if sweep.is_some() {
+        report(samples_supplier.set_sweep(sweep));
+    }
+    if args.agc {
+        report(samples_supplier.set_gain(None));
+    }
+    if let Some(gain) = args.gain {
+        report(samples_supplier.set_gain(Some(gain)));
+    }
+    for (name, gain) in &args.gain_element {
+        report(samples_supplier.set_gain_element(name, *gain));
+    }
+    if let Some(ppm) = args.ppm {
+        report(samples_supplier.set_ppm(ppm));
+    }
+    if args.dc_removal != DcMode::Off {
+        report(samples_supplier.set_dc_mode(args.dc_removal));
+    }
+    if args.iq_correction {
+        report(samples_supplier.set_iq_correction(true));
+    }
+    if let Some(path) = &args.calibration {
+        report(samples_supplier.set_calibration(Calibration::load(path)?));
+    }
+    report(samples_supplier.set_detection_threshold(args.detection_threshold));
+    if args.detect {
+        report(samples_supplier.set_detection(true));
+    }
+    if let Some(path) = &args.record {
+        report(samples_supplier.start_recording(path));
+    }
+    if args.phase_difference {
+        report(samples_supplier.set_cross_spectrum(true));
+    }
+
+    // One plot per channel, followed by the phase difference if computed
+    let num_channels = samples_supplier.num_channels();
+    let mut plots = Vec::new();
+    for _ in 0..num_channels {
+        plots.push(unsafe { WaterfallPlot::new(gl.clone(), PlotKind::Power)? });
+    }
+    if samples_supplier.cross_spectrum() {
+        plots.push(unsafe { WaterfallPlot::new(gl.clone(), PlotKind::Phase)? });
+    }
+    let size = window.window().inner_size();
+    unsafe {
+        arrange_plots(
+            &mut plots,
+            num_channels,
+            args.layout,
+            size.width,
+            size.height,
+        )
+    };
 
-    samples_supplier.set_frequency(frequency);
-    samples_supplier.set_samplerate(args.samplerate);
-    samples_supplier.set_bandwidth(args.bandwidth);
-    samples_supplier.activate();
+    samples_supplier.activate()?;
+    let mut acquisition = Acquisition::start(samples_supplier)?;
 
     unsafe {
         {
@@ -68,23 +518,68 @@ fn main() {
                 *control_flow = ControlFlow::Wait;
                 match event {
                     Event::LoopDestroyed => {
-                        return;
+                        acquisition.stop();
                     }
                     Event::MainEventsCleared => {
                         window.window().request_redraw();
                     }
                     Event::RedrawRequested(_) => {
-                        waterfallplot.update_plot(samples_supplier.get_block());
-                        window.swap_buffers().unwrap();
+                        // Wait a little for new rows so an idle source doesn't spin the loop
+                        let rows = acquisition.rows(Duration::from_millis(20));
+                        for row in &rows {
+                            let gap = row.stats.has_gap();
+                            for (i, spectrum) in row.spectra.iter().enumerate() {
+                                // Detection runs on the first channel only
+                                let detected: &[bool] = if i == 0 { &row.detected } else { &[] };
+                                plots[i].push_row(spectrum, gap, detected, &row.annotated);
+                            }
+                            if let Some(phase) = &row.phase_difference {
+                                plots[num_channels].push_row(phase, gap, &[], &row.annotated);
+                            }
+                        }
+                        // One row per frame is plenty to follow the levels
+                        if let Some(row) = rows.iter().rev().find(|row| row.stats.blocks > 0) {
+                            levels.add(&row.spectra[0]);
+                            if auto_levels {
+                                if let Some((floor, ceiling)) = levels.range() {
+                                    plots[..num_channels]
+                                        .iter_mut()
+                                        .for_each(|plot| plot.set_range(floor, ceiling));
+                                }
+                            }
+                        }
+                        // Plots don't cover columns left over by a previous arrangement
+                        // or the rounding of their widths
+                        gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+                        gl.clear(glow::COLOR_BUFFER_BIT);
+                        for plot in plots.iter_mut() {
+                            plot.draw();
+                        }
+                        if let Err(error) = window.swap_buffers() {
+                            eprintln!("Cannot swap buffers: {}", error);
+                        }
+
+                        let new_title =
+                            window_title(&acquisition.status(), &gain_stages[gain_stage]);
+                        if new_title != title {
+                            window.window().set_title(&new_title);
+                            title = new_title;
+                        }
                     }
                     Event::WindowEvent { ref event, .. } => match event {
                         WindowEvent::Resized(physical_size) => {
-                            waterfallplot
-                                .set_window_size(physical_size.width, physical_size.height);
+                            arrange_plots(
+                                &mut plots,
+                                num_channels,
+                                args.layout,
+                                physical_size.width,
+                                physical_size.height,
+                            );
                             window.resize(*physical_size);
                         }
                         WindowEvent::CloseRequested => {
-                            waterfallplot.drop();
+                            acquisition.stop();
+                            plots.iter_mut().for_each(|plot| plot.drop());
                             *control_flow = ControlFlow::Exit
                         }
                         WindowEvent::Touch(touch) => match touch.phase {
@@ -92,7 +587,8 @@ fn main() {
                                 last_touch = touch.location.y;
                             }
                             TouchPhase::Moved | TouchPhase::Ended => {
-                                waterfallplot.scroll((last_touch - touch.location.y) as i32);
+                                let rows = (last_touch - touch.location.y) as i32;
+                                plots.iter_mut().for_each(|plot| plot.scroll(rows));
                                 last_touch = touch.location.y;
                             }
                             _ => (),
@@ -107,44 +603,162 @@ fn main() {
                             ..
                         } => match key {
                             glutin::event::VirtualKeyCode::A => {
-                                waterfallplot.incr_offset(10.0);
+                                // Adjusting the levels by hand stops tracking them
+                                auto_levels = false;
+                                plots[..num_channels]
+                                    .iter_mut()
+                                    .for_each(|plot| plot.incr_offset(10.0));
                             }
                             glutin::event::VirtualKeyCode::Z => {
-                                waterfallplot.incr_offset(-10.0);
+                                auto_levels = false;
+                                plots[..num_channels]
+                                    .iter_mut()
+                                    .for_each(|plot| plot.incr_offset(-10.0));
                             }
                             glutin::event::VirtualKeyCode::S => {
-                                waterfallplot.incr_max(10.0);
+                                auto_levels = false;
+                                plots[..num_channels]
+                                    .iter_mut()
+                                    .for_each(|plot| plot.incr_max(10.0));
                             }
                             glutin::event::VirtualKeyCode::X => {
-                                waterfallplot.incr_max(-10.0);
+                                auto_levels = false;
+                                plots[..num_channels]
+                                    .iter_mut()
+                                    .for_each(|plot| plot.incr_max(-10.0));
                             }
                             glutin::event::VirtualKeyCode::D => {
-                                waterfallplot.incr_min(10.0);
+                                auto_levels = false;
+                                plots[..num_channels]
+                                    .iter_mut()
+                                    .for_each(|plot| plot.incr_min(10.0));
                             }
                             glutin::event::VirtualKeyCode::C => {
-                                waterfallplot.incr_min(-10.0);
+                                auto_levels = false;
+                                plots[..num_channels]
+                                    .iter_mut()
+                                    .for_each(|plot| plot.incr_min(-10.0));
+                            }
+                            glutin::event::VirtualKeyCode::F => {
+                                acquisition.send(|s| s.set_fft_size(s.fft_size() * 2));
+                            }
+                            glutin::event::VirtualKeyCode::V => {
+                                acquisition.send(|s| s.set_fft_size(s.fft_size() / 2));
+                            }
+                            glutin::event::VirtualKeyCode::H => {
+                                acquisition.send(|s| s.set_averaging(s.averaging() * 2));
+                            }
+                            glutin::event::VirtualKeyCode::N => {
+                                acquisition.send(|s| s.set_averaging(s.averaging() / 2));
+                            }
+                            glutin::event::VirtualKeyCode::M => {
+                                acquisition
+                                    .send(|s| s.set_averaging_mode(s.averaging_mode().next()));
+                            }
+                            glutin::event::VirtualKeyCode::Space => {
+                                acquisition.send(|s| {
+                                    s.reset_averaging();
+                                    Ok(())
+                                });
+                            }
+                            glutin::event::VirtualKeyCode::O => {
+                                // Cycle through the usual Welch overlaps
+                                acquisition.send(|s| {
+                                    let overlap = match s.overlap() {
+                                        o if o < 0.5 => 0.5,
+                                        o if o < 0.75 => 0.75,
+                                        _ => 0.0,
+                                    };
+                                    s.set_overlap(overlap)
+                                });
+                            }
+                            glutin::event::VirtualKeyCode::W => {
+                                acquisition
+                                    .send(|s| s.set_window_function(s.window_function().next()));
+                            }
+                            glutin::event::VirtualKeyCode::G => {
+                                let stage = gain_stage;
+                                acquisition.send(move |s| s.step_gain(stage, true));
+                            }
+                            glutin::event::VirtualKeyCode::B => {
+                                let stage = gain_stage;
+                                acquisition.send(move |s| s.step_gain(stage, false));
+                            }
+                            glutin::event::VirtualKeyCode::E => {
+                                gain_stage = (gain_stage + 1) % gain_stages.len();
+                            }
+                            glutin::event::VirtualKeyCode::T => {
+                                acquisition.send(|s| s.toggle_agc());
+                            }
+                            glutin::event::VirtualKeyCode::L => {
+                                if let Some((floor, ceiling)) = levels.range() {
+                                    plots[..num_channels]
+                                        .iter_mut()
+                                        .for_each(|plot| plot.set_range(floor, ceiling));
+                                }
+                            }
+                            glutin::event::VirtualKeyCode::K => {
+                                auto_levels = !auto_levels;
+                                println!(
+                                    "Automatic levels {}",
+                                    if auto_levels { "enabled" } else { "disabled" }
+                                );
+                            }
+                            glutin::event::VirtualKeyCode::J => {
+                                acquisition.send(|s| {
+                                    let mode = s.dc_mode().next();
+                                    match s.set_dc_mode(mode) {
+                                        // Skip the hardware mode on devices without it
+                                        Err(error) if mode == DcMode::Hardware => {
+                                            eprintln!("{}", error);
+                                            s.set_dc_mode(mode.next())
+                                        }
+                                        result => result,
+                                    }
+                                });
+                            }
+                            glutin::event::VirtualKeyCode::I => {
+                                acquisition.send(|s| s.set_iq_correction(!s.iq_correction()));
+                            }
+                            glutin::event::VirtualKeyCode::Q => match args.reference {
+                                Some(reference) => {
+                                    acquisition.send(move |s| s.calibrate_ppm(reference))
+                                }
+                                None => eprintln!("No --reference carrier to calibrate on"),
+                            },
+                            glutin::event::VirtualKeyCode::Y => {
+                                acquisition.send(|s| s.set_detection(!s.detection()));
+                            }
+                            glutin::event::VirtualKeyCode::P => {
+                                let status = acquisition.status();
+                                print_signals(&status.signals, status.power_unit);
+                            }
+                            glutin::event::VirtualKeyCode::R => {
+                                acquisition.send(|s| {
+                                    if s.is_recording() {
+                                        s.stop_recording()
+                                    } else {
+                                        s.start_recording(&Recorder::default_path())
+                                    }
+                                });
                             }
                             glutin::event::VirtualKeyCode::PageUp => {
-                                frequency += 10e6 as u32;
-                                samples_supplier.set_frequency(frequency);
+                                acquisition.send(|s| s.set_frequency(s.frequency() + 10e6));
                             }
                             glutin::event::VirtualKeyCode::PageDown => {
-                                frequency -= 10e6 as u32;
-                                samples_supplier.set_frequency(frequency);
+                                acquisition.send(|s| s.set_frequency(s.frequency() - 10e6));
                             }
                             glutin::event::VirtualKeyCode::Right => {
-                                frequency += 1e6 as u32;
-                                samples_supplier.set_frequency(frequency);
+                                acquisition.send(|s| s.set_frequency(s.frequency() + 1e6));
                             }
                             glutin::event::VirtualKeyCode::Left => {
-                                frequency -= 1e6 as u32;
-                                samples_supplier.set_frequency(frequency);
+                                acquisition.send(|s| s.set_frequency(s.frequency() - 1e6));
                             }
                             glutin::event::VirtualKeyCode::Up => {
-                                waterfallplot.scroll(-100);
+                                plots.iter_mut().for_each(|plot| plot.scroll(-100));
                             }
                             glutin::event::VirtualKeyCode::Down => {
-                                waterfallplot.scroll(100);
+                                plots.iter_mut().for_each(|plot| plot.scroll(100));
                             }
                             _ => (),
                         },
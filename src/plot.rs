@@ -1,33 +1,136 @@
+use crate::error::{Error, Result};
 use glow::*;
 use glow::{Context, HasContext, Texture};
+use std::rc::Rc;
 
 const SHADER_VERSION: &str = "#version 140";
-const TEXTURE_WIDTH: u32 = 2048;
+const INITIAL_TEXTURE_WIDTH: usize = 2048;
+// Wider spectra are reduced to this many columns, keeping peaks
+const MAX_TEXTURE_WIDTH: usize = 4096;
 const TEXTURE_HEIGHT: u32 = 1024;
 const NUM_TILES: usize = 8;
 const ZTEXTURE: usize = NUM_TILES;
 const MAX_HEIGHT: usize = NUM_TILES * TEXTURE_HEIGHT as usize;
+// Power, detected bins and annotated bins
+const SPECTRUM_ROWS: usize = 3;
+
+// Allocate storage for the waterfall tiles with the given width
+unsafe fn allocate_waterfall_textures(gl: &Context, textures: &[Texture], width: usize) {
+    let level = 0;
+    let internal_format: i32 = glow::RGBA as i32;
+    let format: u32 = glow::RGBA;
+    let border = 0;
+    let ty = glow::UNSIGNED_BYTE;
+
+    for texture in textures {
+        gl.bind_texture(glow::TEXTURE_2D, Some(*texture));
+        gl.tex_image_2d(
+            glow::TEXTURE_2D,
+            level,
+            internal_format,
+            width as i32,
+            TEXTURE_HEIGHT as i32,
+            border,
+            format,
+            ty,
+            None,
+        );
+        gl.tex_parameter_i32(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_MAG_FILTER,
+            glow::LINEAR as i32,
+        );
+        gl.tex_parameter_i32(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_MIN_FILTER,
+            glow::LINEAR as i32,
+        );
+        gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_S, glow::REPEAT as i32);
+        gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_T, glow::REPEAT as i32);
+    }
+}
+
+// Allocate storage for a row of spectrum power values, followed by rows
+// flagging the bins of detected signals and of annotations
+unsafe fn allocate_spectrum_texture(gl: &Context, texture: Texture, width: usize) {
+    gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+    gl.tex_image_2d(
+        glow::TEXTURE_2D,
+        0,
+        glow::R32F as i32,
+        width as i32,
+        SPECTRUM_ROWS as i32,
+        0,
+        glow::RED,
+        glow::FLOAT,
+        None,
+    );
+    // Float textures are not filterable everywhere, the shader uses texelFetch
+    gl.tex_parameter_i32(
+        glow::TEXTURE_2D,
+        glow::TEXTURE_MAG_FILTER,
+        glow::NEAREST as i32,
+    );
+    gl.tex_parameter_i32(
+        glow::TEXTURE_2D,
+        glow::TEXTURE_MIN_FILTER,
+        glow::NEAREST as i32,
+    );
+}
+
+/// What the rows pushed to a plot hold
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlotKind {
+    // Linear power, shown in dB
+    Power,
+    // Phase in radians, spread over the whole colormap from -π to π
+    Phase,
+}
+
+/// Arrangement of the waterfalls of several channels
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// One pane per channel
+    SideBySide,
+    /// All channels blended in one pane
+    Overlay,
+}
 
 pub struct WaterfallPlot {
-    gl: Context,
+    // Shared with the other plots of the window
+    gl: Rc<Context>,
+    kind: PlotKind,
     waterfall_fb: Framebuffer,
-    waterfall_textures: [Texture; NUM_TILES as usize + 1],
+    waterfall_textures: [Texture; NUM_TILES + 1],
+    // Latest spectrum row, read by the waterfall program
+    spectrum_texture: Texture,
+    // Width of the waterfall and spectrum textures
+    texture_width: usize,
+    // Spectrum row reduced to the texture width, followed by its detected and annotated bins
+    spectrum: Vec<f32>,
     source_texture: usize,
     target_texture: usize,
     render_texture: usize,
+    // Area of the window the plot is drawn to
+    viewport_x: i32,
     window_width: i32,
     window_height: i32,
+    // Weight of the plot when blended over the ones drawn before
+    opacity: f32,
     power_offset: f32,
     power_max: f32,
     power_min: f32,
     // Programs
-    waterfall_program: Option<NativeProgram>,
-    colormap_program: Option<NativeProgram>,
+    waterfall_program: NativeProgram,
+    colormap_program: NativeProgram,
     // Uniforms
-    u_samples: Option<UniformLocation>,
     u_y_offset: Option<UniformLocation>,
+    u_gap: Option<UniformLocation>,
+    u_wf_resolution: Option<UniformLocation>,
     y_offset: usize,
     u_resolution: Option<UniformLocation>,
+    u_origin: Option<UniformLocation>,
+    u_opacity: Option<UniformLocation>,
     u_power_offset: Option<UniformLocation>,
     u_power_scale: Option<UniformLocation>,
     // Control variables
@@ -41,8 +144,8 @@ impl WaterfallPlot {
         gl: &glow::Context,
         vertex_src: &str,
         fragment_src: &str,
-    ) -> Option<NativeProgram> {
-        let program: NativeProgram = gl.create_program().expect("Cannot create program");
+    ) -> Result<NativeProgram> {
+        let program: NativeProgram = gl.create_program().map_err(Error::Gl)?;
 
         let shader_sources = [
             (glow::VERTEX_SHADER, vertex_src),
@@ -52,13 +155,14 @@ impl WaterfallPlot {
         let mut shaders = Vec::with_capacity(shader_sources.len());
 
         for (shader_type, shader_source) in shader_sources.iter() {
-            let shader = gl
-                .create_shader(*shader_type)
-                .expect("Cannot create shader");
+            let shader = gl.create_shader(*shader_type).map_err(Error::Gl)?;
             gl.shader_source(shader, &format!("{}\n{}", SHADER_VERSION, shader_source));
             gl.compile_shader(shader);
             if !gl.get_shader_compile_status(shader) {
-                panic!("{}", gl.get_shader_info_log(shader));
+                let log = gl.get_shader_info_log(shader);
+                gl.delete_shader(shader);
+                gl.delete_program(program);
+                return Err(Error::Gl(format!("Cannot compile shader: {}", log)));
             }
             gl.attach_shader(program, shader);
             shaders.push(shader);
@@ -66,7 +170,9 @@ impl WaterfallPlot {
 
         gl.link_program(program);
         if !gl.get_program_link_status(program) {
-            panic!("{}", gl.get_program_info_log(program));
+            let log = gl.get_program_info_log(program);
+            gl.delete_program(program);
+            return Err(Error::Gl(format!("Cannot link program: {}", log)));
         }
 
         for shader in shaders {
@@ -74,57 +180,33 @@ impl WaterfallPlot {
             gl.delete_shader(shader);
         }
 
-        Some(program)
+        Ok(program)
     }
 
-    pub unsafe fn new(gl: Context) -> Self {
+    pub unsafe fn new(gl: Rc<Context>, kind: PlotKind) -> Result<Self> {
         // Create a pair of textures that will be used to create our waterfall
         // - One serves as destination for the framebuffer render
         // - The other is the last rendered texture, that we use to copy from
         // We will switch roles between them every new frame
-        let waterfall_textures: [Texture; NUM_TILES as usize + 1] = [(); NUM_TILES as usize + 1]
-            .map(|_| gl.create_texture().expect("Cannot create texture"));
-
-        let level = 0;
-        let internal_format: i32 = glow::RGBA as i32;
-        let format: u32 = glow::RGBA;
-        let border = 0;
-        let ty = glow::UNSIGNED_BYTE;
-
-        for texture in waterfall_textures {
-            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
-            gl.tex_image_2d(
-                glow::TEXTURE_2D,
-                level,
-                internal_format,
-                TEXTURE_WIDTH as i32,
-                TEXTURE_HEIGHT as i32,
-                border,
-                format,
-                ty,
-                None,
-            );
-            gl.tex_parameter_i32(
-                glow::TEXTURE_2D,
-                glow::TEXTURE_MAG_FILTER,
-                glow::LINEAR as i32,
-            );
-            gl.tex_parameter_i32(
-                glow::TEXTURE_2D,
-                glow::TEXTURE_MIN_FILTER,
-                glow::LINEAR as i32,
-            );
-            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_S, glow::REPEAT as i32);
-            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_T, glow::REPEAT as i32);
-        }
+        let waterfall_textures: [Texture; NUM_TILES + 1] = (0..=NUM_TILES)
+            .map(|_| gl.create_texture().map_err(Error::Gl))
+            .collect::<Result<Vec<_>>>()?
+            .try_into()
+            .unwrap();
+        let texture_width = INITIAL_TEXTURE_WIDTH;
+        allocate_waterfall_textures(&gl, &waterfall_textures, texture_width);
+
+        let spectrum_texture = gl.create_texture().map_err(Error::Gl)?;
+        allocate_spectrum_texture(&gl, spectrum_texture, texture_width);
+        let spectrum = vec![0.0; SPECTRUM_ROWS * texture_width];
 
         // Framebuffer that will be used to render to our waterfall texture
-        let waterfall_fb = gl.create_framebuffer().expect("Cannot create framebuffer");
+        let waterfall_fb = gl.create_framebuffer().map_err(Error::Gl)?;
 
         //
         let window_width: i32 = 1024;
         let window_height: i32 = 1024;
-        let power_offset: f32 = 30.0;
+        let power_offset: f32 = 100.0;
         let power_min: f32 = 0.0;
         let power_max: f32 = 100.0;
         let time_position: usize = 0;
@@ -140,50 +222,54 @@ impl WaterfallPlot {
         );
 
         // Define program 1 and its uniforms
-        let waterfall_program = WaterfallPlot::create_program(&gl, vs_quad_src, fs_waterfall_src);
-        gl.use_program(waterfall_program);
+        let waterfall_program = WaterfallPlot::create_program(&gl, vs_quad_src, fs_waterfall_src)?;
+        gl.use_program(Some(waterfall_program));
 
-        let u_power_offset =
-            gl.get_uniform_location(waterfall_program.unwrap(), "waterfallScaleAdd");
-        let u_power_scale =
-            gl.get_uniform_location(waterfall_program.unwrap(), "waterfallScaleMult");
+        let u_power_offset = gl.get_uniform_location(waterfall_program, "waterfallScaleAdd");
+        let u_power_scale = gl.get_uniform_location(waterfall_program, "waterfallScaleMult");
         gl.uniform_1_f32(u_power_offset.as_ref(), power_offset);
         gl.uniform_1_f32(u_power_scale.as_ref(), 1.0 / (power_max - power_min).abs());
 
-        let u_resolution = gl.get_uniform_location(waterfall_program.unwrap(), "resolution");
+        let u_wf_resolution = gl.get_uniform_location(waterfall_program, "resolution");
         gl.uniform_2_f32(
-            u_resolution.as_ref(),
-            TEXTURE_WIDTH as f32,
+            u_wf_resolution.as_ref(),
+            texture_width as f32,
             TEXTURE_HEIGHT as f32,
         );
 
-        let u_waterfall_texture =
-            gl.get_uniform_location(waterfall_program.unwrap(), "waterfallTexture");
+        let u_waterfall_texture = gl.get_uniform_location(waterfall_program, "waterfallTexture");
         gl.uniform_1_i32(u_waterfall_texture.as_ref(), 0);
 
-        let u_samples = gl.get_uniform_location(waterfall_program.unwrap(), "samples");
+        let u_spectrum = gl.get_uniform_location(waterfall_program, "spectrum");
+        gl.uniform_1_i32(u_spectrum.as_ref(), 1);
 
-        let u_y_offset = gl.get_uniform_location(waterfall_program.unwrap(), "yOffset");
+        let u_y_offset = gl.get_uniform_location(waterfall_program, "yOffset");
+        let u_gap = gl.get_uniform_location(waterfall_program, "gap");
+        let u_phase = gl.get_uniform_location(waterfall_program, "phase");
+        gl.uniform_1_i32(u_phase.as_ref(), (kind == PlotKind::Phase) as i32);
 
         gl.bind_framebuffer(glow::FRAMEBUFFER, None);
 
         // Define program 2 and its uniforms
-        let colormap_program = WaterfallPlot::create_program(&gl, vs_quad_src, fs_colormap_src);
-        gl.use_program(colormap_program);
+        let colormap_program = WaterfallPlot::create_program(&gl, vs_quad_src, fs_colormap_src)?;
+        gl.use_program(Some(colormap_program));
 
-        let u_resolution = gl.get_uniform_location(colormap_program.unwrap(), "resolution");
+        let u_resolution = gl.get_uniform_location(colormap_program, "resolution");
         gl.uniform_2_f32(
             u_resolution.as_ref(),
             window_width as f32,
             window_height as f32,
         );
 
-        let u_cm_offset = gl.get_uniform_location(colormap_program.unwrap(), "offset");
-        let u_waterfall_texture_0 =
-            gl.get_uniform_location(colormap_program.unwrap(), "waterfallTexture0");
+        let u_origin = gl.get_uniform_location(colormap_program, "originX");
+        gl.uniform_1_f32(u_origin.as_ref(), 0.0);
+        let u_opacity = gl.get_uniform_location(colormap_program, "opacity");
+        gl.uniform_1_f32(u_opacity.as_ref(), 1.0);
+
+        let u_cm_offset = gl.get_uniform_location(colormap_program, "offset");
+        let u_waterfall_texture_0 = gl.get_uniform_location(colormap_program, "waterfallTexture0");
         gl.uniform_1_i32(u_waterfall_texture_0.as_ref(), 0);
-        let u_waterfall_texture_1 =
-            gl.get_uniform_location(colormap_program.unwrap(), "waterfallTexture1");
+        let u_waterfall_texture_1 = gl.get_uniform_location(colormap_program, "waterfallTexture1");
         gl.uniform_1_i32(u_waterfall_texture_1.as_ref(), 1);
 
         gl.clear_color(0.0, 0.0, 0.0, 1.0);
@@ -195,67 +281,149 @@ impl WaterfallPlot {
         let target_texture = 0;
         let render_texture = ZTEXTURE;
 
-        Self {
+        Ok(Self {
             gl,
+            kind,
             waterfall_fb,
             waterfall_textures,
+            spectrum_texture,
+            texture_width,
+            spectrum,
             source_texture,
             target_texture,
             render_texture,
+            viewport_x: 0,
             window_width,
             window_height,
+            opacity: 1.0,
             power_offset,
             power_max,
             power_min,
             waterfall_program,
             colormap_program,
-            u_samples,
             u_y_offset,
+            u_gap,
+            u_wf_resolution,
             y_offset,
             u_resolution,
+            u_origin,
+            u_opacity,
             u_power_offset,
             u_power_scale,
             u_cm_offset,
             time_position,
             scroll_advance,
-        }
+        })
     }
 
     pub unsafe fn drop(&mut self) {
-        self.gl.delete_program(self.colormap_program.unwrap());
-        self.gl.delete_program(self.waterfall_program.unwrap());
+        self.gl.delete_program(self.colormap_program);
+        self.gl.delete_program(self.waterfall_program);
     }
 
-    pub unsafe fn update_plot(&mut self, samples_block: &[f32]) {
-        // Update colormap program logic
-        // Because we want to support scrolling, we select here the 2 textures that
-        // are going to get drawn to the screen: top (cm_tex0) and bottom (cm_tex1).
-        // cm_offset controls how much is drawn of each one.
-        let scroll_offset = (self.y_offset as i32 - self.time_position as i32 + 1)
-            .rem_euclid(MAX_HEIGHT as i32) as usize;
-        let cm_offset = scroll_offset.rem_euclid(TEXTURE_HEIGHT as usize);
+    // Reallocate the textures for spectrum rows of a new width, clearing the history
+    unsafe fn set_texture_width(&mut self, width: usize) {
+        let gl = &self.gl;
+        self.texture_width = width;
+        self.spectrum.resize(SPECTRUM_ROWS * width, 0.0);
+        allocate_waterfall_textures(gl, &self.waterfall_textures, width);
+        allocate_spectrum_texture(gl, self.spectrum_texture, width);
 
-        let tex_idx0 = (scroll_offset / TEXTURE_HEIGHT as usize) as usize;
-        let tex_idx1 = (tex_idx0 as i32 - 1).rem_euclid(NUM_TILES as i32) as usize;
-        let cm_tex0 = if tex_idx0 == self.target_texture {
-            self.render_texture
-        } else {
-            tex_idx0
-        };
-        let cm_tex1 = tex_idx1;
+        gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.waterfall_fb));
+        for texture in self.waterfall_textures {
+            gl.framebuffer_texture_2d(
+                glow::FRAMEBUFFER,
+                glow::COLOR_ATTACHMENT0,
+                glow::TEXTURE_2D,
+                Some(texture),
+                0,
+            );
+            gl.clear(glow::COLOR_BUFFER_BIT);
+        }
+        gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+
+        gl.use_program(Some(self.waterfall_program));
+        gl.uniform_2_f32(
+            self.u_wf_resolution.as_ref(),
+            width as f32,
+            TEXTURE_HEIGHT as f32,
+        );
+    }
+
+    // Upload a spectrum row and its detected and annotated bins, reducing them
+    // to at most MAX_TEXTURE_WIDTH columns
+    unsafe fn upload_spectrum(
+        &mut self,
+        samples_block: &[f32],
+        detected: &[bool],
+        annotated: &[bool],
+    ) {
+        let factor = samples_block.len().div_ceil(MAX_TEXTURE_WIDTH).max(1);
+        let width = samples_block.len().div_ceil(factor);
+        if width != self.texture_width {
+            self.set_texture_width(width);
+        }
+
+        // Keep the strongest bin of each group so narrow signals stay visible,
+        // phases can't be combined and are only picked
+        let (values, marks) = self.spectrum.split_at_mut(width);
+        for (value, bins) in values.iter_mut().zip(samples_block.chunks(factor)) {
+            *value = match self.kind {
+                PlotKind::Power => bins.iter().copied().fold(f32::MIN, f32::max),
+                PlotKind::Phase => bins[0],
+            };
+        }
+        for (row, flags) in marks.chunks_mut(width).zip([detected, annotated]) {
+            row.fill(0.0);
+            for (mark, bins) in row.iter_mut().zip(flags.chunks(factor)) {
+                *mark = if bins.contains(&true) { 1.0 } else { 0.0 };
+            }
+        }
+
+        let gl = &self.gl;
+        let bytes = std::slice::from_raw_parts(
+            self.spectrum.as_ptr() as *const u8,
+            self.spectrum.len() * std::mem::size_of::<f32>(),
+        );
+        gl.active_texture(glow::TEXTURE1);
+        gl.bind_texture(glow::TEXTURE_2D, Some(self.spectrum_texture));
+        gl.tex_sub_image_2d(
+            glow::TEXTURE_2D,
+            0,
+            0,
+            0,
+            width as i32,
+            SPECTRUM_ROWS as i32,
+            glow::RED,
+            glow::FLOAT,
+            PixelUnpackData::Slice(bytes),
+        );
+    }
+
+    // Render a new spectrum row into the waterfall textures, `gap` marks rows
+    // built from non-contiguous samples, `detected` the bins of detected
+    // signals and `annotated` the bins covered by annotations, if any
+    pub unsafe fn push_row(
+        &mut self,
+        samples_block: &[f32],
+        gap: bool,
+        detected: &[bool],
+        annotated: &[bool],
+    ) {
+        self.upload_spectrum(samples_block, detected, annotated);
 
         // Actual OpenGL calls start here
         let gl = &self.gl;
 
         // Update waterfall texture first
-        gl.use_program(self.waterfall_program);
+        gl.use_program(Some(self.waterfall_program));
 
-        // Update samples uniform with current FFT window and time position to update
-        gl.uniform_1_f32_slice(self.u_samples.as_ref(), samples_block);
+        // Update time position to paint, the spectrum texture was uploaded above
         gl.uniform_1_u32(
             self.u_y_offset.as_ref(),
             self.y_offset.rem_euclid(TEXTURE_HEIGHT as usize) as u32,
         );
+        gl.uniform_1_i32(self.u_gap.as_ref(), gap as i32);
         // Use the framebuffer to render the updated waterfall to another texture
         gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.waterfall_fb));
         gl.active_texture(glow::TEXTURE0);
@@ -270,30 +438,23 @@ impl WaterfallPlot {
             Some(self.waterfall_textures[self.render_texture]),
             0,
         );
-        gl.viewport(0, 0, TEXTURE_WIDTH as i32, TEXTURE_HEIGHT as i32);
+        gl.viewport(0, 0, self.texture_width as i32, TEXTURE_HEIGHT as i32);
+        // The alpha channel holds data, drawing the plots may have left blending on
+        gl.disable(glow::BLEND);
         gl.draw_arrays(glow::TRIANGLE_STRIP, 0, 4);
         gl.draw_buffer(glow::COLOR_ATTACHMENT0);
-
-        // Draw final scene (only colormap atm)
-        gl.use_program(self.colormap_program);
         gl.bind_framebuffer(glow::FRAMEBUFFER, None);
-        gl.active_texture(glow::TEXTURE0);
-        gl.bind_texture(glow::TEXTURE_2D, Some(self.waterfall_textures[cm_tex0]));
-        gl.active_texture(glow::TEXTURE1);
-        gl.bind_texture(glow::TEXTURE_2D, Some(self.waterfall_textures[cm_tex1]));
-        gl.uniform_1_u32(self.u_cm_offset.as_ref(), cm_offset as u32);
-        gl.viewport(0, 0, self.window_width, self.window_height);
-        gl.draw_arrays(glow::TRIANGLE_STRIP, 0, 4);
 
-        // Update waterfall logic 
-        self.y_offset = (self.y_offset + 1) % MAX_HEIGHT;  // absolute position of line to paint
-        if self.y_offset % (TEXTURE_HEIGHT as usize) == 0 {
+        // Update waterfall logic
+        self.y_offset = (self.y_offset + 1) % MAX_HEIGHT; // absolute position of line to paint
+        let tile_line = self.y_offset % (TEXTURE_HEIGHT as usize);
+        if tile_line == 0 {
             // Change target to the next texture
             let last = self.target_texture;
-            self.target_texture = (self.target_texture + 1) % NUM_TILES as usize;
+            self.target_texture = (self.target_texture + 1) % NUM_TILES;
             // keeping last as source texture avoids visual artifact but loses 1 tile
             (self.render_texture, self.source_texture) = (ZTEXTURE, last);
-        } else if self.y_offset % (TEXTURE_HEIGHT as usize) == 1 {
+        } else if tile_line == 1 {
             // special case due to visual artifact fix
             (self.render_texture, self.source_texture) = (self.target_texture, ZTEXTURE);
         } else {
@@ -303,33 +464,82 @@ impl WaterfallPlot {
         }
 
         // If scroll is locked, time position needs to be updated
-        if self.scroll_advance == false
-        && self.time_position < ((NUM_TILES - 2) * (TEXTURE_HEIGHT as usize) - 1) as usize
+        if !self.scroll_advance
+            && self.time_position < (NUM_TILES - 2) * (TEXTURE_HEIGHT as usize) - 1
         {
-            self.time_position = self.time_position + 1;
+            self.time_position += 1;
         }
+    }
 
+    // Draw the waterfall to the screen
+    pub unsafe fn draw(&mut self) {
+        // Update colormap program logic
+        // Because we want to support scrolling, we select here the 2 textures that
+        // are going to get drawn to the screen: top (cm_tex0) and bottom (cm_tex1).
+        // cm_offset controls how much is drawn of each one.
+        let scroll_offset = (self.y_offset as i32 - self.time_position as i32)
+            .rem_euclid(MAX_HEIGHT as i32) as usize;
+        let cm_offset = scroll_offset.rem_euclid(TEXTURE_HEIGHT as usize);
+
+        // The tile holding the last painted line lives in the last rendered texture,
+        // which push_row left as the source texture
+        let last_tile = (self.y_offset + MAX_HEIGHT - 1) % MAX_HEIGHT / TEXTURE_HEIGHT as usize;
+        let tex_idx0 = scroll_offset / TEXTURE_HEIGHT as usize;
+        let tex_idx1 = (tex_idx0 as i32 - 1).rem_euclid(NUM_TILES as i32) as usize;
+        let cm_tex0 = if tex_idx0 == last_tile {
+            self.source_texture
+        } else {
+            tex_idx0
+        };
+        let cm_tex1 = tex_idx1;
+
+        // Draw final scene (only colormap atm)
+        let gl = &self.gl;
+        gl.use_program(Some(self.colormap_program));
+        gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+        gl.active_texture(glow::TEXTURE0);
+        gl.bind_texture(glow::TEXTURE_2D, Some(self.waterfall_textures[cm_tex0]));
+        gl.active_texture(glow::TEXTURE1);
+        gl.bind_texture(glow::TEXTURE_2D, Some(self.waterfall_textures[cm_tex1]));
+        gl.uniform_1_u32(self.u_cm_offset.as_ref(), cm_offset as u32);
+        gl.uniform_1_f32(self.u_opacity.as_ref(), self.opacity);
+        if self.opacity < 1.0 {
+            gl.enable(glow::BLEND);
+            gl.blend_func(glow::SRC_ALPHA, glow::ONE_MINUS_SRC_ALPHA);
+        } else {
+            gl.disable(glow::BLEND);
+        }
+        gl.viewport(self.viewport_x, 0, self.window_width, self.window_height);
+        gl.draw_arrays(glow::TRIANGLE_STRIP, 0, 4);
     }
 
-    pub unsafe fn set_window_size(&mut self, width: u32, height: u32) {
+    // Draw to the `width` by `height` area of the window starting at column `x`
+    pub unsafe fn set_viewport(&mut self, x: i32, width: u32, height: u32) {
         let gl = &self.gl;
+        self.viewport_x = x;
         self.window_width = width as i32;
         self.window_height = height as i32;
-        gl.use_program(self.colormap_program);
+        gl.use_program(Some(self.colormap_program));
         gl.uniform_2_f32(self.u_resolution.as_ref(), width as f32, height as f32);
+        gl.uniform_1_f32(self.u_origin.as_ref(), x as f32);
+    }
+
+    // Blend the plot over what is already drawn, 1.0 covers it
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity;
     }
 
     pub unsafe fn incr_offset(&mut self, val: f32) {
         let gl = &self.gl;
         self.power_offset += val;
-        gl.use_program(self.waterfall_program);
+        gl.use_program(Some(self.waterfall_program));
         gl.uniform_1_f32(self.u_power_offset.as_ref(), self.power_offset);
     }
 
     pub unsafe fn incr_max(&mut self, val: f32) {
         let gl = &self.gl;
         self.power_max += val;
-        gl.use_program(self.waterfall_program);
+        gl.use_program(Some(self.waterfall_program));
         gl.uniform_1_f32(
             self.u_power_scale.as_ref(),
             1.0 / (self.power_max - self.power_min).abs(),
@@ -339,7 +549,21 @@ impl WaterfallPlot {
     pub unsafe fn incr_min(&mut self, val: f32) {
         let gl = &self.gl;
         self.power_min += val;
-        gl.use_program(self.waterfall_program);
+        gl.use_program(Some(self.waterfall_program));
+        gl.uniform_1_f32(
+            self.u_power_scale.as_ref(),
+            1.0 / (self.power_max - self.power_min).abs(),
+        );
+    }
+
+    // Map powers from `floor` to `ceiling` dB onto the whole colormap
+    pub unsafe fn set_range(&mut self, floor: f32, ceiling: f32) {
+        let gl = &self.gl;
+        self.power_offset = -floor;
+        self.power_min = 0.0;
+        self.power_max = (ceiling - floor).max(1.0);
+        gl.use_program(Some(self.waterfall_program));
+        gl.uniform_1_f32(self.u_power_offset.as_ref(), self.power_offset);
         gl.uniform_1_f32(
             self.u_power_scale.as_ref(),
             1.0 / (self.power_max - self.power_min).abs(),
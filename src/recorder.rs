@@ -0,0 +1,99 @@
+use crate::error::{io, Result};
+use crate::source::sigmf::{recording_paths, Capture, Global, Metadata, SIGMF_VERSION};
+use chrono::{SecondsFormat, Utc};
+use num::complex::Complex32;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Writer of received samples into a SigMF recording
+pub struct Recorder {
+    // Dataset file, samples are stored as cf32_le
+    data: BufWriter<File>,
+    // Path of the metadata file, written when the recording ends
+    meta_path: PathBuf,
+    // Metadata accumulated during the recording
+    metadata: Metadata,
+    // Number of samples written to the dataset
+    samples_written: u64,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, samplerate: f64, hardware: String) -> Result<Self> {
+        let (meta_path, data_path) = recording_paths(path);
+        let data = BufWriter::new(File::create(&data_path).map_err(io(format!(
+            "Cannot create SigMF dataset {}",
+            data_path.display()
+        )))?);
+        println!("Recording to {}", data_path.display());
+
+        let metadata = Metadata {
+            global: Global {
+                datatype: String::from("cf32_le"),
+                version: String::from(SIGMF_VERSION),
+                sample_rate: Some(samplerate),
+                hw: Some(hardware),
+                recorder: Some(String::from(env!("CARGO_PKG_NAME"))),
+                description: None,
+            },
+            captures: Vec::new(),
+            annotations: Vec::new(),
+        };
+
+        Ok(Self {
+            data,
+            meta_path,
+            metadata,
+            samples_written: 0,
+        })
+    }
+
+    /// Default recording name, based on the current time
+    pub fn default_path() -> PathBuf {
+        PathBuf::from(format!("waterfall-{}", Utc::now().format("%Y%m%dT%H%M%SZ")))
+    }
+
+    /// Start a new capture segment at the current position
+    pub fn add_capture(&mut self, frequency: f64) {
+        let sample_start = self.samples_written;
+        // A retune before any sample was written replaces the previous segment
+        self.metadata
+            .captures
+            .retain(|c| c.sample_start != sample_start);
+        self.metadata.captures.push(Capture {
+            sample_start,
+            frequency: Some(frequency),
+            datetime: Some(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+        });
+    }
+
+    pub fn write(&mut self, samples: &[Complex32]) -> Result<()> {
+        for sample in samples {
+            self.data
+                .write_all(&sample.re.to_le_bytes())
+                .and_then(|_| self.data.write_all(&sample.im.to_le_bytes()))
+                .map_err(io("Cannot write SigMF dataset"))?;
+        }
+        self.samples_written += samples.len() as u64;
+        Ok(())
+    }
+
+    /// Flush the dataset and write the metadata file
+    pub fn finish(&mut self) -> Result<()> {
+        self.data
+            .flush()
+            .map_err(io("Cannot write SigMF dataset"))?;
+        let meta_file = File::create(&self.meta_path).map_err(io(format!(
+            "Cannot create SigMF metadata {}",
+            self.meta_path.display()
+        )))?;
+        serde_json::to_writer_pretty(meta_file, &self.metadata)
+            .map_err(|error| io("Cannot write SigMF metadata")(error.into()))?;
+        println!(
+            "Recorded {} samples to {}",
+            self.samples_written,
+            self.meta_path.display()
+        );
+        Ok(())
+    }
+}
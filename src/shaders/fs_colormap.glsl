@@ -4,17 +4,40 @@ uniform vec2 resolution;
 uniform sampler2D waterfallTexture0;
 uniform sampler2D waterfallTexture1;
 uniform uint offset;
+uniform float originX;  // first column of the plot in the window
+uniform float opacity;  // weight when blended over other plots
 
 out vec4 color;
 
-void main() {
+// Waterfall value at a position of the plot, in window pixels
+vec4 waterfall(vec2 position) {
     float offset_norm = float(offset) / 1024.0 - 1.0;
-    vec2 coord = (gl_FragCoord.xy / resolution.xy) + vec2(0.0, offset_norm);
-    vec4 value;
+    vec2 coord = (position / resolution.xy) + vec2(0.0, offset_norm);
     if (coord.y >= 0) {
-        value = texture(waterfallTexture0, coord);
+        return texture(waterfallTexture0, coord);
+    } else {
+        return texture(waterfallTexture1, coord);
+    }
+}
+
+// Annotated bins have a cleared alpha channel
+bool annotated(vec2 position) {
+    return waterfall(position).w < 0.5;
+}
+
+void main() {
+    vec2 position = gl_FragCoord.xy - vec2(originX, 0.0);
+    vec4 value = waterfall(position);
+    bool inside = annotated(position + vec2(1.0, 0.0)) && annotated(position - vec2(1.0, 0.0))
+        && annotated(position + vec2(0.0, 1.0)) && annotated(position - vec2(0.0, 1.0));
+    if (value.y > 0.5 && position.x < 12.0) {
+        // Marker on the left edge for rows with data gaps
+        color = vec4(1.0, 0.0, 1.0, opacity);
+    } else if (value.w < 0.5 && !inside) {
+        // Outline around annotations
+        color = vec4(1.0, 1.0, 1.0, opacity);
     } else {
-        value = texture(waterfallTexture1, coord);
+        // Detected signals are lightened, the blue channel flags their bins
+        color = vec4(mix(TurboColormap(value.x), vec3(1.0), 0.3 * value.z), opacity);
     }
-    color = vec4(TurboColormap(value.x), 1.0);
 }
\ No newline at end of file
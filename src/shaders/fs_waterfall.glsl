@@ -3,22 +3,33 @@ in vec2 vert;
 out vec4 color;
 
 uniform vec2 resolution;
-uniform float samples[2048];
+uniform sampler2D spectrum;  // power of each frequency bin, DC at the center, then detected and annotated bins
 uniform sampler2D waterfallTexture;
 uniform float waterfallScaleAdd;
 uniform float waterfallScaleMult;
 
 uniform uint yOffset;  // position for painting new line
+uniform bool gap;  // new line has missing samples, flagged in the green channel
+uniform bool phase;  // spectrum holds phases in radians rather than powers
 
 const float scale = 10.0 / log(10);
+const float PI = 3.14159265;
 
 void main() {
 
     if (uint(gl_FragCoord.y) == yOffset) {
-        int freq_bin = int( gl_FragCoord.x + 1024 ) % 2048;
-        float bin_power = scale * log(samples[freq_bin]);
-        float val = (bin_power + waterfallScaleAdd) * waterfallScaleMult;
-        color = vec4(val, val, val, 1.0);
+        int freq_bin = int(gl_FragCoord.x);
+        float bin_value = texelFetch(spectrum, ivec2(freq_bin, 0), 0).r;
+        float val;
+        if (phase) {
+            val = bin_value / (2.0 * PI) + 0.5;
+        } else {
+            val = (scale * log(bin_value) + waterfallScaleAdd) * waterfallScaleMult;
+        }
+        float detected = texelFetch(spectrum, ivec2(freq_bin, 1), 0).r;
+        // Textures are cleared to an opaque alpha, so annotations clear it instead
+        float annotated = texelFetch(spectrum, ivec2(freq_bin, 2), 0).r;
+        color = vec4(val, gap ? 1.0 : 0.0, detected, 1.0 - annotated);
     } else {
         vec2 coord = gl_FragCoord.xy / resolution.xy;
         color = texture(waterfallTexture, coord);
@@ -1,134 +1,1742 @@
+use crate::averaging::{Averager, AveragingMode};
+use crate::calibration::Calibration;
+use crate::correction::{interpolate_bins, DcBlocker, DcMode, IqCorrector};
+use crate::detector::{DetectedSignal, Detector};
+use crate::error::{Error, Result};
+use crate::recorder::Recorder;
+use crate::sweep::Sweep;
+use crate::units::parse_frequency;
+use crate::window::{Window, WindowFunction, WindowScaling};
 use num::complex::Complex32;
 use num::Zero;
 use rustfft::{Fft, FftPlanner};
+use std::f64::consts::TAU;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-const NUM_SAMPLES: usize = 2048;
+mod generator;
+mod raw;
+mod rtltcp;
+pub mod sigmf;
+mod soapy;
+
+pub use generator::{demo_scenario, GeneratorSource, Signal};
+pub use raw::{RawSource, SampleFormat};
+pub use rtltcp::RtlTcpSource;
+pub use sigmf::SigmfSource;
+pub use soapy::{list_devices, SoapySource};
+
+pub const MIN_FFT_SIZE: usize = 256;
+pub const MAX_FFT_SIZE: usize = 65536;
+// Largest reference oscillator error corrected, and searched for when calibrating on a carrier
+const MAX_PPM_ERROR: f64 = 100.0;
+// Power of a reference carrier above the median of the searched bins, in dB
+const MIN_CARRIER_SNR: f32 = 10.0;
+// Bins on each side of DC replaced by interpolation, covering the main lobe of the usual windows
+const DC_HALF_WIDTH: usize = 2;
+
+/// Outcome of reading a block from a `SampleSource`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadStatus {
+    // Buffer was completely filled with new samples
+    Ok,
+    // Samples were lost, the buffer contents must be discarded
+    Overflow,
+    // Source has no more samples to provide
+    EndOfStream,
+}
+
+/// Data integrity of a single spectrum row
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RowStats {
+    // FFT blocks that made it into the row
+    pub blocks: u32,
+    // Reads that reported lost samples
+    pub overflows: u32,
+    // FFT blocks skipped because their samples were unusable
+    pub dropped_blocks: u32,
+}
+
+impl RowStats {
+    /// Whether the row is not built from contiguous samples
+    pub fn has_gap(&self) -> bool {
+        self.overflows > 0 || self.dropped_blocks > 0
+    }
+}
+
+/// Data integrity accumulated since the start of the acquisition
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Statistics {
+    pub rows: u64,
+    pub gap_rows: u64,
+    pub overflows: u64,
+    pub dropped_blocks: u64,
+}
+
+impl Statistics {
+    fn add(&mut self, row: &RowStats) {
+        self.rows += 1;
+        self.gap_rows += row.has_gap() as u64;
+        self.overflows += row.overflows as u64;
+        self.dropped_blocks += row.dropped_blocks as u64;
+    }
+}
+
+/// Valid settings of a gain stage in dB
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GainRange {
+    pub min: f64,
+    pub max: f64,
+    // Resolution of the setting, 0 if continuous
+    pub step: f64,
+}
+
+impl GainRange {
+    /// Closest valid setting to `gain`
+    pub fn clamp(&self, gain: f64) -> f64 {
+        let gain = gain.max(self.min).min(self.max);
+        if self.step > 0.0 {
+            let steps = ((gain - self.min) / self.step).round();
+            (self.min + steps * self.step).min(self.max)
+        } else {
+            gain
+        }
+    }
+
+    /// Increment used for runtime adjustment
+    pub fn increment(&self) -> f64 {
+        if self.step > 0.0 {
+            self.step.max(1.0)
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Features supported by a `SampleSource`
+#[derive(Clone, Debug, Default)]
+pub struct Capabilities {
+    // Whether the center frequency can be changed
+    pub tunable: bool,
+    // Supported (min, max) frequency ranges, empty if unknown
+    pub frequency_ranges: Vec<(f64, f64)>,
+    // Range of the overall gain, if it can be changed
+    pub gain: Option<GainRange>,
+    // Individually adjustable gain elements, e.g. LNA, VGA and AMP on HackRF
+    pub gain_elements: Vec<(String, GainRange)>,
+    // Whether automatic gain control is available
+    pub agc: bool,
+}
+
+impl Capabilities {
+    pub fn supports_frequency(&self, frequency: f64) -> bool {
+        self.frequency_ranges.is_empty()
+            || self
+                .frequency_ranges
+                .iter()
+                .any(|&(min, max)| frequency >= min && frequency <= max)
+    }
+
+    /// Closest supported frequency, unchanged if the ranges are unknown
+    pub fn clamp_frequency(&self, frequency: f64) -> f64 {
+        self.frequency_ranges
+            .iter()
+            .map(|&(min, max)| frequency.clamp(min, max))
+            .min_by(|a, b| (a - frequency).abs().total_cmp(&(b - frequency).abs()))
+            .unwrap_or(frequency)
+    }
+}
+
+/// Offset between the frontend LO and the requested frequency, keeps the DC spike away
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoOffset {
+    // Offset in Hz, zero tunes the LO right onto the requested frequency
+    Fixed(f64),
+    // Half the analog bandwidth, moving the DC spike to the edge of the band
+    Auto,
+}
+
+impl FromStr for LoOffset {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(LoOffset::Auto),
+            _ => parse_frequency(s).map(LoOffset::Fixed),
+        }
+    }
+}
+
+impl fmt::Display for LoOffset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoOffset::Fixed(offset) => write!(f, "{} Hz", offset),
+            LoOffset::Auto => f.write_str("auto"),
+        }
+    }
+}
+
+/// Provider of complex baseband samples: a radio, a file, a generator...
+pub trait SampleSource: Send {
+    /// Fill the whole `buffer` with consecutive samples
+    fn read(&mut self, buffer: &mut [Complex32]) -> Result<ReadStatus>;
+
+    /// Number of coherent channels delivered by `read_channels`
+    fn num_channels(&self) -> usize {
+        1
+    }
+
+    /// Fill one buffer per channel with consecutive samples taken at the same time
+    fn read_channels(&mut self, buffers: &mut [&mut [Complex32]]) -> Result<ReadStatus> {
+        match buffers {
+            [buffer] => self.read(buffer),
+            _ => Err(Error::Unsupported(String::from(
+                "Source has a single channel",
+            ))),
+        }
+    }
+
+    fn set_frequency(&mut self, frequency: f64) -> Result<()>;
+
+    fn set_samplerate(&mut self, samplerate: f64) -> Result<()>;
+
+    fn set_bandwidth(&mut self, bandwidth: f64) -> Result<()>;
+
+    /// Set the overall gain in dB, or automatic gain control if `None`
+    fn set_gain(&mut self, _gain: Option<f64>) -> Result<()> {
+        Err(Error::Unsupported(String::from(
+            "Gain control not supported by this source",
+        )))
+    }
+
+    /// Set the gain of an individual element, as listed in the capabilities
+    fn set_gain_element(&mut self, _name: &str, _gain: f64) -> Result<()> {
+        Err(Error::Unsupported(String::from(
+            "Gain elements not supported by this source",
+        )))
+    }
+
+    /// Current overall gain in dB, if known
+    fn gain(&self) -> Option<f64> {
+        None
+    }
+
+    /// Current gain of an individual element in dB, if known
+    fn gain_element(&self, _name: &str) -> Option<f64> {
+        None
+    }
+
+    /// Set the reference oscillator correction in parts per million, returns
+    /// the correction applied, which may be coarser than requested
+    fn set_ppm(&mut self, _ppm: f64) -> Result<f64> {
+        Err(Error::Unsupported(String::from(
+            "Frequency correction not supported by this source",
+        )))
+    }
+
+    /// Start producing samples
+    fn activate(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities;
+
+    /// Short human readable name of the source
+    fn description(&self) -> String;
+
+    /// Tune the frontend LO `offset` Hz away from the frequencies passed to `set_frequency`
+    fn set_lo_offset(&mut self, _offset: f64) -> Result<()> {
+        Err(Error::Unsupported(String::from(
+            "LO offset not supported by this source",
+        )))
+    }
+
+    /// Enable or disable the DC offset removal of the device itself
+    fn set_dc_offset_mode(&mut self, _automatic: bool) -> Result<()> {
+        Err(Error::Unsupported(String::from(
+            "DC offset removal not supported by this source",
+        )))
+    }
+
+    /// Frequency at the center of the delivered samples, if it may differ from the
+    /// requested one, e.g. an LO offset that the device does not compensate
+    fn tuned_frequency(&self) -> Option<f64> {
+        None
+    }
+
+    /// Center frequency dictated by the source itself, e.g. recording metadata
+    fn frequency(&self) -> Option<f64> {
+        None
+    }
+
+    /// Frequency ranges in Hz marked by the source over the last samples read,
+    /// e.g. recording annotations
+    fn annotations(&self) -> &[(f64, f64)] {
+        &[]
+    }
+
+    /// Sample rate dictated by the source itself, e.g. recording metadata
+    fn samplerate(&self) -> Option<f64> {
+        None
+    }
+}
+
+// Check that the FFT size is a power of two within MIN_FFT_SIZE..=MAX_FFT_SIZE
+fn check_fft_size(fft_size: usize) -> Result<()> {
+    if fft_size.is_power_of_two() && (MIN_FFT_SIZE..=MAX_FFT_SIZE).contains(&fft_size) {
+        Ok(())
+    } else {
+        Err(Error::InvalidSetting(format!(
+            "FFT size must be a power of two between {} and {}",
+            MIN_FFT_SIZE, MAX_FFT_SIZE
+        )))
+    }
+}
+
+/// Numerically controlled oscillator shifting the received samples in frequency
+struct Mixer {
+    // Shift in Hz
+    shift: f64,
+    // Phase increment per sample in radians
+    step: f64,
+    // Current phase in radians
+    phase: f64,
+}
+
+impl Mixer {
+    fn new() -> Self {
+        Self {
+            shift: 0.0,
+            step: 0.0,
+            phase: 0.0,
+        }
+    }
+
+    fn set_shift(&mut self, shift: f64, samplerate: f64) {
+        self.shift = shift;
+        self.step = if samplerate > 0.0 {
+            TAU * shift / samplerate
+        } else {
+            0.0
+        };
+    }
+
+    // Shift a block of samples, starting at the current phase. The same block of
+    // every channel goes through here before `advance`, keeping them coherent
+    fn apply(&self, samples: &mut [Complex32]) {
+        if self.step == 0.0 {
+            return;
+        }
+        let mut phase = self.phase;
+        for sample in samples.iter_mut() {
+            *sample *= Complex32::from_polar(1.0, phase as f32);
+            phase = (phase + self.step) % TAU;
+        }
+    }
+
+    fn advance(&mut self, num_samples: usize) {
+        self.phase = (self.phase + self.step * num_samples as f64) % TAU;
+    }
+}
+
+/// Sample buffers and spectrum state of one receive channel
+struct Channel {
+    // Latest fft_size received samples, the tail is reused by the next block
+    history: Vec<Complex32>,
+    // Buffer for receiving new samples
+    buffer_read: Vec<Complex32>,
+    // Buffer for the windowed block being transformed
+    buffer_c32: Vec<Complex32>,
+    // Buffer for computing squared magnitude of FFT output
+    buffer_magsq: Vec<f32>,
+    // Combines the segments into spectrum rows
+    averager: Averager,
+    // Running mean subtraction, for DcMode::Mean
+    dc_blocker: DcBlocker,
+    // Gain and phase imbalance estimation and correction
+    iq_corrector: IqCorrector,
+}
+
+impl Channel {
+    fn new(averager: Averager, fft_size: usize) -> Self {
+        Self {
+            history: vec![Complex32::zero(); fft_size],
+            buffer_read: vec![Complex32::zero(); fft_size],
+            buffer_c32: vec![Complex32::zero(); fft_size],
+            buffer_magsq: vec![f32::zero(); fft_size],
+            averager,
+            dc_blocker: DcBlocker::new(),
+            iq_corrector: IqCorrector::new(),
+        }
+    }
+
+    fn resize(&mut self, fft_size: usize) {
+        self.history.resize(fft_size, Complex32::zero());
+        self.buffer_read.resize(fft_size, Complex32::zero());
+        self.buffer_c32.resize(fft_size, Complex32::zero());
+        self.buffer_magsq.resize(fft_size, 0.0);
+        self.averager.resize(fft_size);
+    }
+
+    // Append the first `num_new` samples of buffer_read to the history and
+    // transform it into buffer_c32
+    fn transform(
+        &mut self,
+        num_new: usize,
+        window: &Window,
+        fft: &dyn Fft<f32>,
+        scratch: &mut [Complex32],
+    ) {
+        let fft_size = self.history.len();
+        self.history.copy_within(num_new.., 0);
+        self.history[fft_size - num_new..].copy_from_slice(&self.buffer_read[..num_new]);
+
+        self.buffer_c32.copy_from_slice(&self.history);
+        window.apply(&mut self.buffer_c32);
+
+        // Compute in-place FFT with scratch memory to avoid allocations
+        fft.process_with_scratch(&mut self.buffer_c32, scratch);
+
+        // Convert FFT bins to squared magnitude, swapping halves so that
+        // negative frequencies come first
+        let half = fft_size / 2;
+        for (i, bin) in self.buffer_c32.iter().enumerate() {
+            self.buffer_magsq[(i + half) % fft_size] = bin.norm_sqr();
+        }
+    }
+}
+
+/// Pacing of sources that could otherwise deliver samples faster than real time
+struct Throttle {
+    // Rate at which samples are delivered
+    samplerate: f64,
+    // Time reference, set when the first samples are delivered
+    start: Option<Instant>,
+    // Samples delivered since `start`
+    samples: u64,
+}
+
+impl Throttle {
+    fn new() -> Self {
+        Self {
+            samplerate: 0.0,
+            start: None,
+            samples: 0,
+        }
+    }
+
+    fn set_samplerate(&mut self, samplerate: f64) {
+        self.samplerate = samplerate;
+        self.start = None;
+        self.samples = 0;
+    }
+
+    /// Wait until `num_samples` more samples are due
+    fn wait(&mut self, num_samples: usize) {
+        let start = *self.start.get_or_insert_with(Instant::now);
+        self.samples += num_samples as u64;
+        if self.samplerate > 0.0 {
+            let target = Duration::from_secs_f64(self.samples as f64 / self.samplerate);
+            let elapsed = start.elapsed();
+            if target > elapsed {
+                std::thread::sleep(target - elapsed);
+            }
+        }
+    }
+}
 
 pub struct DataSupplier {
     // Number of segments that are averaged
     averaging: u32,
-    // Precomputed scale factor for mean calculation
-    averaging_inv: f32,
-    // SoapySDR device
-    device: soapysdr::Device,
+    // Source of input samples
+    source: Box<dyn SampleSource>,
+    // Features reported by the source
+    capabilities: Capabilities,
     // Tuning frequency
     frequency: f64,
     // Sample rate
     samplerate: f64,
     // Analog bandwidth
     bandwidth: f64,
+    // Planner reused when the FFT size changes
+    planner: FftPlanner<f32>,
+    // Number of FFT bins
+    fft_size: usize,
     // FFT instance optimized for our desired size
     fft: Arc<dyn Fft<f32>>,
-    // Source of input samples
-    rx_stream: soapysdr::RxStream<Complex32>,
-    // Buffer for receiving samples
-    buffer_c32: [Complex32; NUM_SAMPLES],
+    // Window applied to each block before the FFT
+    window: Window,
+    // Power correction compensating for the window
+    window_scaling: WindowScaling,
+    // Fraction of each block shared with the previous one
+    overlap: f32,
+    // Buffers of each channel of the source, the first one feeds sweeps,
+    // detection and recording
+    channels: Vec<Channel>,
+    // Whether the channel histories hold contiguous samples that can be reused
+    history_valid: bool,
     // Scratch memory for FFT, avoids per-loop allocation
-    scratch: [Complex32; NUM_SAMPLES],
-    // Buffer for computing squared magnitude of FFT output
-    buffer_magsq: [f32; NUM_SAMPLES],
+    scratch: Vec<Complex32>,
+    // Whether the cross-spectrum of the first two channels is computed
+    cross_spectrum: bool,
+    // Sum of the cross-spectra of the blocks in the current row
+    cross_sum: Vec<Complex32>,
+    // Phase difference between the first two channels in each bin of the last row
+    phase_difference: Vec<f32>,
+    // Optional SigMF recording of the received samples
+    recorder: Option<Recorder>,
+    // Whether automatic gain control is enabled
+    agc: bool,
+    // Description of the current gain settings
+    gain_status: String,
+    // Data integrity of the last row
+    row_stats: RowStats,
+    // Data integrity of all rows so far
+    statistics: Statistics,
+    // Wideband sweep replacing the fixed tuning, if any
+    sweep: Option<Sweep>,
+    // Averaging state of each sweep step, swapped in while the step is measured
+    sweep_averagers: Vec<Averager>,
+    // Pieces of all sweep steps stitched side by side
+    sweep_row: Vec<f32>,
+    // Requested LO offset, the source default is used if not set
+    lo_offset: Option<LoOffset>,
+    // Moves the requested frequency back to the center when the source could not
+    mixer: Mixer,
+    // How the DC spike is removed
+    dc_mode: DcMode,
+    // Whether IQ imbalance is corrected
+    iq_correction: bool,
+    // Signal detection run on each row, if enabled
+    detector: Option<Detector>,
+    // Detection threshold above the local noise in dB
+    detection_threshold: f32,
+    // Bins of the last row covered by annotations of the source
+    annotated: Vec<bool>,
+    // Offsets turning dBFS into dBm, if loaded
+    calibration: Option<Calibration>,
+    // Offset currently applied to the rows, in dB, rows are in dBFS without it
+    level_offset: Option<f64>,
+    // Reference oscillator correction in parts per million
+    ppm: f64,
+    // Part of the correction applied by adjusting the tune requests, for
+    // sources that cannot correct themselves or only in coarse steps
+    tuning_ppm: f64,
 }
 
 impl DataSupplier {
-    pub fn new(averaging: u32) -> Self {
-        let averaging_inv = 1.0 / averaging as f32;
-        let fft = FftPlanner::new().plan_fft_forward(NUM_SAMPLES);
+    pub fn new(
+        source: Box<dyn SampleSource>,
+        averaging: u32,
+        averaging_mode: AveragingMode,
+        alpha: f32,
+        fft_size: usize,
+        window_function: WindowFunction,
+        window_scaling: WindowScaling,
+    ) -> Result<Self> {
+        check_fft_size(fft_size)?;
+        let averager = Averager::new(averaging_mode, alpha, fft_size);
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(fft_size);
+        let window = Window::new(window_function, fft_size);
 
-        let buffer_c32 = [Complex32::zero(); NUM_SAMPLES];
-        let scratch = [Complex32::zero(); NUM_SAMPLES];
-        let buffer_magsq = [f32::zero(); NUM_SAMPLES];
+        let channels = (0..source.num_channels())
+            .map(|_| Channel::new(averager.clone(), fft_size))
+            .collect();
+        let scratch = vec![Complex32::zero(); fft.get_inplace_scratch_len()];
 
-        let args: soapysdr::Args = "driver=hackrf".into();
-        let device = soapysdr::Device::new(args).expect("No SoapySDR device found");
+        let capabilities = source.capabilities();
+        println!("Using source {}", source.description());
 
         let frequency = 0.0;
         let samplerate = 0.0;
         let bandwidth = 0.0;
 
-        device
-            .set_gain(soapysdr::Direction::Rx, 0, 30.0)
-            .expect("Cannot set gain");
-
-        let rx_stream: soapysdr::RxStream<Complex32> = device.rx_stream(&[0]).unwrap();
-
-        Self {
+        Ok(Self {
             averaging,
-            averaging_inv,
-            device,
+            source,
+            capabilities,
             frequency,
             samplerate,
             bandwidth,
+            planner,
+            fft_size,
             fft,
-            rx_stream,
-            buffer_c32,
+            window,
+            window_scaling,
+            overlap: 0.0,
+            channels,
+            history_valid: false,
             scratch,
-            buffer_magsq,
-        }
+            cross_spectrum: false,
+            cross_sum: Vec::new(),
+            phase_difference: Vec::new(),
+            recorder: None,
+            agc: false,
+            gain_status: String::new(),
+            row_stats: RowStats::default(),
+            statistics: Statistics::default(),
+            sweep: None,
+            sweep_averagers: Vec::new(),
+            sweep_row: Vec::new(),
+            lo_offset: None,
+            mixer: Mixer::new(),
+            dc_mode: DcMode::Off,
+            iq_correction: false,
+            detector: None,
+            detection_threshold: 10.0,
+            annotated: Vec::new(),
+            calibration: None,
+            level_offset: None,
+            ppm: 0.0,
+            tuning_ppm: 0.0,
+        })
     }
 
-    pub fn set_frequency(&mut self, frequency: u32) {
-        self.frequency = frequency as f64;
-        let tuning_args: soapysdr::Args = "OFFSET=50e3".into();
-        self.device
-            .set_frequency(soapysdr::Direction::Rx, 0, self.frequency, tuning_args)
-            .expect("Cannot set frequency");
+    /// Tune to `frequency`, clamped to the nearest range supported by the source
+    pub fn set_frequency(&mut self, frequency: f64) -> Result<()> {
+        if !self.capabilities.tunable {
+            // Files and generators can't be tuned, the frequency only labels their samples
+            if !frequency.is_finite() || frequency < 0.0 {
+                return Err(Error::InvalidSetting(format!(
+                    "Invalid frequency {}",
+                    frequency
+                )));
+            }
+            self.frequency = frequency;
+            println!("Nominal center frequency set to {}", self.frequency);
+            return Ok(());
+        }
+        if self.sweep.is_some() {
+            return Err(Error::Unsupported(String::from(
+                "Cannot tune while sweeping",
+            )));
+        }
+        let clamped = self.capabilities.clamp_frequency(frequency);
+        if clamped.is_nan() || clamped < 0.0 {
+            return Err(Error::InvalidSetting(format!(
+                "Invalid frequency {}",
+                frequency
+            )));
+        }
+        if clamped != frequency {
+            println!(
+                "Frequency {} out of the device range, using {}",
+                frequency, clamped
+            );
+        }
+        self.tune(clamped)?;
+        self.frequency = clamped;
         println!("Frequency set to {}", self.frequency);
+        Ok(())
+    }
+
+    // Tune the source and compensate whatever part of the LO offset it leaves in the samples
+    fn tune(&mut self, frequency: f64) -> Result<()> {
+        // A fast reference puts the LO above the request, ask for less
+        let request = frequency / (1.0 + self.tuning_ppm * 1e-6);
+        self.source.set_frequency(request)?;
+        let shift = self
+            .source
+            .tuned_frequency()
+            .map_or(0.0, |tuned| tuned - request);
+        if shift != self.mixer.shift {
+            println!("Shifting samples by {} Hz to center the spectrum", shift);
+        }
+        self.mixer.set_shift(shift, self.samplerate());
+        // Averages and holds were measured at the previous frequency, sweep
+        // steps keep their own aside
+        for channel in self.channels.iter_mut() {
+            channel.averager.reset();
+        }
+        // DC offset and IQ imbalance change with the LO, estimate them anew
+        for channel in self.channels.iter_mut() {
+            channel.dc_blocker.reset();
+            channel.iq_corrector.reset();
+        }
+        // Samples from before the retune don't continue into the next block
+        self.history_valid = false;
+        // Recorded samples are not shifted, they are centred where the tuner is
+        if let Some(recorder) = &mut self.recorder {
+            recorder.add_capture(frequency + shift);
+        }
+        Ok(())
     }
 
-    pub fn set_samplerate(&mut self, samplerate: u32) {
-        self.samplerate = samplerate as f64;
-        self.device
-            .set_sample_rate(soapysdr::Direction::Rx, 0, self.samplerate)
-            .expect("Cannot set sample rate");
+    pub fn set_samplerate(&mut self, samplerate: f64) -> Result<()> {
+        // A rate dictated by the source wins, e.g. a recording played back at another
+        // rate would show every frequency scaled
+        let samplerate = match self.source.samplerate() {
+            Some(dictated) if dictated != samplerate => {
+                println!(
+                    "Sample rate {} given by the source overrides {}",
+                    dictated, samplerate
+                );
+                dictated
+            }
+            _ => samplerate,
+        };
+        // The sweep steps follow from the sample rate, refuse one they can't be run with
+        if let Some(sweep) = self.sweep {
+            self.check_sweep(&sweep, samplerate, self.fft_size)?;
+        }
+        self.source.set_samplerate(samplerate)?;
+        self.samplerate = samplerate;
+        self.mixer.set_shift(self.mixer.shift, self.samplerate());
+        self.sweep_averagers.clear();
         println!("Sample rate set to {}", self.samplerate);
+        Ok(())
     }
 
-    pub fn set_bandwidth(&mut self, bandwidth: u32) {
-        self.bandwidth = bandwidth as f64;
-        self.device
-            .set_bandwidth(soapysdr::Direction::Rx, 0, self.bandwidth)
-            .expect("Cannot set bandwidth");
+    pub fn set_bandwidth(&mut self, bandwidth: f64) -> Result<()> {
+        self.source.set_bandwidth(bandwidth)?;
+        self.bandwidth = bandwidth;
         println!("Bandwidth set to {}", self.bandwidth);
+        if self.lo_offset == Some(LoOffset::Auto) {
+            self.apply_lo_offset()?;
+        }
+        Ok(())
+    }
+
+    /// Set how far the frontend LO is tuned from the requested frequency
+    pub fn set_lo_offset(&mut self, offset: LoOffset) -> Result<()> {
+        if !self.capabilities.tunable {
+            return Err(Error::Unsupported(String::from("Source cannot be tuned")));
+        }
+        self.lo_offset = Some(offset);
+        self.apply_lo_offset()?;
+        println!("LO offset set to {}", offset);
+        Ok(())
+    }
+
+    // Pass the LO offset to the source and retune with it
+    fn apply_lo_offset(&mut self) -> Result<()> {
+        let offset = match self.lo_offset {
+            Some(LoOffset::Fixed(offset)) => offset,
+            Some(LoOffset::Auto) => self.bandwidth / 2.0,
+            None => return Ok(()),
+        };
+        self.source.set_lo_offset(offset)?;
+        if self.frequency > 0.0 && self.sweep.is_none() {
+            self.tune(self.frequency)?;
+        }
+        Ok(())
+    }
+
+    /// Set the overall gain in dB, or enable automatic gain control if `None`
+    pub fn set_gain(&mut self, gain: Option<f64>) -> Result<()> {
+        let result = match (gain, self.capabilities.gain) {
+            (Some(_), None) => Err(Error::Unsupported(String::from(
+                "Gain control not supported by this source",
+            ))),
+            (Some(gain), Some(range)) => {
+                let gain = range.clamp(gain);
+                self.source.set_gain(Some(gain)).map(|()| {
+                    self.agc = false;
+                    println!("Gain set to {} dB", gain);
+                })
+            }
+            (None, _) if !self.capabilities.agc => Err(Error::Unsupported(String::from(
+                "Automatic gain not supported by this source",
+            ))),
+            (None, _) => self.source.set_gain(None).map(|()| {
+                self.agc = true;
+                println!("Gain set to automatic");
+            }),
+        };
+        self.update_gain_status();
+        self.update_level_offset();
+        result
+    }
+
+    pub fn set_gain_element(&mut self, name: &str, gain: f64) -> Result<()> {
+        let range = self
+            .capabilities
+            .gain_elements
+            .iter()
+            .find(|(element, _)| element.eq_ignore_ascii_case(name))
+            .map(|(element, range)| (element.clone(), *range));
+        let result = match range {
+            Some((element, range)) => {
+                let gain = range.clamp(gain);
+                self.source.set_gain_element(&element, gain).map(|()| {
+                    self.agc = false;
+                    println!("{} gain set to {} dB", element, gain);
+                })
+            }
+            None => Err(Error::InvalidSetting(format!(
+                "Unknown gain element {}, available: {}",
+                name,
+                self.gain_stages()[1..].join(", ")
+            ))),
+        };
+        self.update_gain_status();
+        self.update_level_offset();
+        result
+    }
+
+    /// Names of the adjustable gain stages, the overall gain comes first
+    pub fn gain_stages(&self) -> Vec<String> {
+        std::iter::once(String::from("Gain"))
+            .chain(
+                self.capabilities
+                    .gain_elements
+                    .iter()
+                    .map(|(n, _)| n.clone()),
+            )
+            .collect()
+    }
+
+    /// Step the gain stage with index `stage` in `gain_stages` up or down
+    pub fn step_gain(&mut self, stage: usize, up: bool) -> Result<()> {
+        let sign = if up { 1.0 } else { -1.0 };
+        if stage == 0 {
+            match self.capabilities.gain {
+                Some(range) => {
+                    let gain = self.source.gain().unwrap_or(range.min);
+                    self.set_gain(Some(gain + sign * range.increment()))
+                }
+                None => Err(Error::Unsupported(String::from(
+                    "Gain control not supported by this source",
+                ))),
+            }
+        } else if let Some((name, range)) = self.capabilities.gain_elements.get(stage - 1).cloned()
+        {
+            let gain = self.source.gain_element(&name).unwrap_or(range.min);
+            self.set_gain_element(&name, gain + sign * range.increment())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn toggle_agc(&mut self) -> Result<()> {
+        if self.agc {
+            let gain = self.source.gain().or(self.capabilities.gain.map(|r| r.min));
+            self.set_gain(gain)
+        } else {
+            self.set_gain(None)
+        }
     }
 
-    pub fn activate(&mut self) {
-        self.rx_stream
-            .activate(None)
-            .expect("Cannot activate reception stream");
+    fn update_gain_status(&mut self) {
+        self.gain_status = if self.agc {
+            String::from("AGC")
+        } else {
+            let elements: Vec<String> = self
+                .capabilities
+                .gain_elements
+                .iter()
+                .filter_map(|(name, _)| {
+                    let gain = self.source.gain_element(name)?;
+                    Some(format!("{} {}", name, gain))
+                })
+                .collect();
+            match self.source.gain() {
+                Some(gain) if elements.is_empty() => format!("{} dB", gain),
+                Some(gain) => format!("{} dB ({})", gain, elements.join(", ")),
+                None => String::new(),
+            }
+        };
     }
 
-    pub fn get_block(&mut self) -> &[f32] {
-        // Reset output buffer
-        self.buffer_magsq.iter_mut().for_each(|x| *x = 0.0);
+    // Look up the calibration offset for the current gain
+    fn update_level_offset(&mut self) {
+        let gain = if self.agc { None } else { self.source.gain() };
+        let offset = match &self.calibration {
+            Some(calibration) => calibration.offset(&self.source.description(), gain),
+            None => return,
+        };
+        if offset.is_none() && self.level_offset.is_some() {
+            eprintln!("No calibration for this gain, levels are in dBFS");
+        }
+        self.level_offset = offset;
+    }
+
+    /// Use `calibration` to show levels in dBm
+    pub fn set_calibration(&mut self, calibration: Calibration) -> Result<()> {
+        self.calibration = Some(calibration);
+        self.update_level_offset();
+        match self.level_offset {
+            Some(offset) => println!("Levels calibrated to dBm, offset {:.1} dB", offset),
+            None => {
+                return Err(Error::InvalidSetting(format!(
+                    "No calibration for {}, levels are in dBFS",
+                    self.source.description()
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    /// Unit of the row levels once converted to dB
+    pub fn power_unit(&self) -> &'static str {
+        match self.level_offset {
+            Some(_) => "dBm",
+            None => "dBFS",
+        }
+    }
+
+    /// Summary of the current gain settings, empty if unknown
+    pub fn gain_status(&self) -> &str {
+        &self.gain_status
+    }
+
+    pub fn ppm(&self) -> f64 {
+        self.ppm
+    }
+
+    /// Correct a reference oscillator off by `ppm` parts per million, through the
+    /// source if it can, otherwise by adjusting the tune requests
+    pub fn set_ppm(&mut self, ppm: f64) -> Result<()> {
+        if !ppm.is_finite() || ppm.abs() > MAX_PPM_ERROR {
+            return Err(Error::InvalidSetting(format!(
+                "Frequency correction {} ppm out of range, at most {} ppm",
+                ppm, MAX_PPM_ERROR
+            )));
+        }
+        let previous = self.tuning_ppm;
+        match self.source.set_ppm(ppm) {
+            // What the source rounded off is left to the tune requests
+            Ok(applied) => self.tuning_ppm = ppm - applied,
+            Err(Error::Unsupported(_)) if self.capabilities.tunable => self.tuning_ppm = ppm,
+            Err(error) => return Err(error),
+        }
+        self.ppm = ppm;
+        // Sweeps retune on the next row anyway
+        if self.tuning_ppm != previous && self.sweep.is_none() && self.frequency > 0.0 {
+            self.tune(self.frequency)?;
+        }
+        println!("Frequency correction set to {} ppm", ppm);
+        Ok(())
+    }
+
+    /// Estimate the reference oscillator error from a carrier known to be at
+    /// `reference` Hz in the last row, and correct it
+    pub fn calibrate_ppm(&mut self, reference: f64) -> Result<()> {
+        if !reference.is_finite() || reference <= 0.0 {
+            return Err(Error::InvalidSetting(format!(
+                "Invalid reference frequency {}",
+                reference
+            )));
+        }
+        let (first_frequency, bin_width) = self.bin_frequencies();
+        let row = match self.sweep {
+            Some(_) => &self.sweep_row,
+            None => &self.channels[0].buffer_magsq,
+        };
+        let bin_of = |frequency: f64| ((frequency - first_frequency) / bin_width).round();
+        let span = (reference * MAX_PPM_ERROR * 1e-6).max(bin_width * 4.0);
+        let first = bin_of(reference - span).max(0.0) as usize;
+        let last = (bin_of(reference + span).max(0.0) as usize).min(row.len());
+        if last < first + 3 {
+            return Err(Error::InvalidSetting(format!(
+                "Reference {} outside of the spectrum",
+                reference
+            )));
+        }
+
+        let db: Vec<f32> = row[first..last].iter().map(|x| 10.0 * x.log10()).collect();
+        let (peak, &peak_db) = db
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap();
+        let mut sorted = db.clone();
+        sorted.sort_by(f32::total_cmp);
+        // An empty row gives an infinite or undefined ratio, not a carrier
+        let snr = peak_db - sorted[sorted.len() / 2];
+        if !snr.is_finite() || snr < MIN_CARRIER_SNR {
+            return Err(Error::InvalidSetting(format!(
+                "No carrier found near {}",
+                reference
+            )));
+        }
+
+        // Parabolic interpolation of the peak for sub-bin accuracy
+        let fraction = if peak > 0 && peak + 1 < db.len() {
+            let (left, right) = (db[peak - 1], db[peak + 1]);
+            let denominator = left - 2.0 * peak_db + right;
+            if denominator != 0.0 {
+                0.5 * (left - right) / denominator
+            } else {
+                0.0
+            }
+        } else {
+            0.0
+        };
+        let measured = first_frequency + ((first + peak) as f64 + fraction as f64) * bin_width;
+
+        // The carrier shows up low by the error of the LO, on top of the current correction
+        let error = (reference - measured) / reference * 1e6;
+        if !error.is_finite() {
+            return Err(Error::InvalidSetting(format!(
+                "Cannot measure the carrier near {}",
+                reference
+            )));
+        }
+        println!(
+            "Reference carrier measured at {:.1} Hz, {:+.2} ppm off",
+            measured, error
+        );
+        self.set_ppm(self.ppm + error)
+    }
+
+    pub fn fft_size(&self) -> usize {
+        self.fft_size
+    }
+
+    /// Change the number of FFT bins, a power of two within MIN_FFT_SIZE..=MAX_FFT_SIZE
+    pub fn set_fft_size(&mut self, fft_size: usize) -> Result<()> {
+        check_fft_size(fft_size)?;
+        if let Some(sweep) = self.sweep {
+            self.check_sweep(&sweep, self.samplerate(), fft_size)?;
+        }
+        self.fft_size = fft_size;
+        self.fft = self.planner.plan_fft_forward(fft_size);
+        for channel in self.channels.iter_mut() {
+            channel.resize(fft_size);
+        }
+        self.history_valid = false;
+        self.scratch
+            .resize(self.fft.get_inplace_scratch_len(), Complex32::zero());
+        self.window = Window::new(self.window.function(), fft_size);
+        self.sweep_averagers.clear();
+        println!("FFT size set to {}", fft_size);
+        Ok(())
+    }
+
+    pub fn averaging(&self) -> u32 {
+        self.averaging
+    }
+
+    /// Set the number of FFT blocks combined into each row
+    pub fn set_averaging(&mut self, averaging: u32) -> Result<()> {
+        if averaging == 0 {
+            return Err(Error::InvalidSetting(String::from(
+                "At least one block must be averaged",
+            )));
+        }
+        self.averaging = averaging;
+        println!("Averaging {} blocks", self.averaging);
+        Ok(())
+    }
+
+    pub fn averaging_mode(&self) -> AveragingMode {
+        self.channels[0].averager.mode()
+    }
+
+    pub fn set_averaging_mode(&mut self, mode: AveragingMode) -> Result<()> {
+        for channel in self.channels.iter_mut() {
+            channel.averager.set_mode(mode);
+        }
+        self.sweep_averagers.clear();
+        println!("Averaging mode set to {}", mode);
+        Ok(())
+    }
+
+    /// Restart exponential averaging and peak/min hold
+    pub fn reset_averaging(&mut self) {
+        for channel in self.channels.iter_mut() {
+            channel.averager.reset();
+        }
+        self.sweep_averagers.clear();
+    }
+
+    pub fn overlap(&self) -> f32 {
+        self.overlap
+    }
+
+    /// Set the fraction of each FFT block that is shared with the previous one
+    pub fn set_overlap(&mut self, overlap: f32) -> Result<()> {
+        if !(0.0..=0.95).contains(&overlap) {
+            return Err(Error::InvalidSetting(format!(
+                "Overlap {:.0}% out of range 0-95%",
+                overlap * 100.0
+            )));
+        }
+        self.overlap = overlap;
+        println!("Overlap set to {:.0}%", self.overlap * 100.0);
+        Ok(())
+    }
+
+    pub fn window_function(&self) -> WindowFunction {
+        self.window.function()
+    }
+
+    pub fn set_window_function(&mut self, function: WindowFunction) -> Result<()> {
+        self.window = Window::new(function, self.fft_size);
+        println!(
+            "Window set to {} (coherent gain {:.2} dB, ENBW {:.2} bins)",
+            function,
+            20.0 * self.window.coherent_gain().log10(),
+            self.window.enbw()
+        );
+        Ok(())
+    }
+
+    /// Center frequency of the spectrum rows, the middle of the covered range when sweeping
+    pub fn frequency(&self) -> f64 {
+        match self.sweep {
+            Some(sweep) => {
+                let centers = sweep.centers(self.samplerate(), self.fft_size);
+                (centers[0] + centers[centers.len() - 1]) / 2.0
+            }
+            None => self.source.frequency().unwrap_or(self.frequency),
+        }
+    }
+
+    pub fn samplerate(&self) -> f64 {
+        self.source.samplerate().unwrap_or(self.samplerate)
+    }
+
+    pub fn dc_mode(&self) -> DcMode {
+        self.dc_mode
+    }
+
+    pub fn set_dc_mode(&mut self, mode: DcMode) -> Result<()> {
+        if mode == DcMode::Hardware {
+            self.source.set_dc_offset_mode(true)?;
+        } else if self.dc_mode == DcMode::Hardware {
+            self.source.set_dc_offset_mode(false)?;
+        }
+        self.dc_mode = mode;
+        for channel in self.channels.iter_mut() {
+            channel.dc_blocker.reset();
+        }
+        println!("DC removal set to {}", mode);
+        Ok(())
+    }
+
+    pub fn iq_correction(&self) -> bool {
+        self.iq_correction
+    }
+
+    /// Enable or disable the automatic IQ gain and phase imbalance correction
+    pub fn set_iq_correction(&mut self, enabled: bool) -> Result<()> {
+        self.iq_correction = enabled;
+        for channel in self.channels.iter_mut() {
+            channel.iq_corrector.reset();
+        }
+        println!(
+            "IQ imbalance correction {}",
+            if enabled { "enabled" } else { "disabled" }
+        );
+        Ok(())
+    }
+
+    pub fn num_channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Last row of `channel`, the whole sweep for the first channel when sweeping
+    pub fn channel_spectrum(&self, channel: usize) -> &[f32] {
+        match self.sweep {
+            Some(_) if channel == 0 => &self.sweep_row,
+            _ => &self.channels[channel].buffer_magsq,
+        }
+    }
+
+    pub fn cross_spectrum(&self) -> bool {
+        self.cross_spectrum
+    }
+
+    /// Compute the phase difference between the first two channels in each bin
+    pub fn set_cross_spectrum(&mut self, enabled: bool) -> Result<()> {
+        if enabled && self.channels.len() < 2 {
+            return Err(Error::Unsupported(String::from(
+                "Cross-spectrum needs two channels",
+            )));
+        }
+        self.cross_spectrum = enabled;
+        self.phase_difference.clear();
+        println!(
+            "Cross-spectrum {}",
+            if enabled { "enabled" } else { "disabled" }
+        );
+        Ok(())
+    }
+
+    /// Phase difference of the last row in radians, if the cross-spectrum is computed
+    pub fn phase_difference(&self) -> Option<&[f32]> {
+        (self.cross_spectrum && !self.phase_difference.is_empty())
+            .then_some(self.phase_difference.as_slice())
+    }
+
+    pub fn detection(&self) -> bool {
+        self.detector.is_some()
+    }
+
+    /// Enable or disable signal detection on each row
+    pub fn set_detection(&mut self, enabled: bool) -> Result<()> {
+        self.detector = enabled.then(|| Detector::new(self.detection_threshold));
+        println!(
+            "Signal detection {}",
+            if enabled { "enabled" } else { "disabled" }
+        );
+        Ok(())
+    }
+
+    /// Set how far above the local noise a bin must be to belong to a signal, in dB
+    pub fn set_detection_threshold(&mut self, threshold: f32) -> Result<()> {
+        if threshold <= 0.0 {
+            return Err(Error::InvalidSetting(String::from(
+                "Detection threshold must be positive",
+            )));
+        }
+        self.detection_threshold = threshold;
+        if let Some(detector) = &mut self.detector {
+            *detector = Detector::new(threshold);
+        }
+        println!("Detection threshold set to {} dB", threshold);
+        Ok(())
+    }
+
+    /// Signals currently tracked by the detector, ordered by frequency
+    pub fn signals(&self) -> Vec<DetectedSignal> {
+        self.detector
+            .as_ref()
+            .map(Detector::signals)
+            .unwrap_or_default()
+    }
+
+    /// Bins of the last row belonging to detected signals, empty without detection
+    pub fn detected_bins(&self) -> &[bool] {
+        self.detector.as_ref().map_or(&[], Detector::detected)
+    }
+
+    /// Bins of the last row covered by annotations of the source
+    pub fn annotated_bins(&self) -> &[bool] {
+        &self.annotated
+    }
+
+    /// Center frequency of the first bin of a row and the spacing of the bins
+    pub fn bin_frequencies(&self) -> (f64, f64) {
+        let bin_width = self.samplerate() / self.fft_size as f64;
+        let first = match self.sweep {
+            Some(sweep) => sweep.start,
+            None => self.frequency() - self.samplerate() / 2.0,
+        };
+        (first, bin_width)
+    }
+
+    // Check that `sweep` can be run with the given sample rate and FFT size
+    fn check_sweep(&self, sweep: &Sweep, samplerate: f64, fft_size: usize) -> Result<()> {
+        if !self.capabilities.tunable {
+            return Err(Error::Unsupported(String::from("Source cannot be tuned")));
+        }
+        if self.is_recording() {
+            return Err(Error::Unsupported(String::from(
+                "Cannot sweep while recording",
+            )));
+        }
+        if self.channels.len() > 1 {
+            return Err(Error::Unsupported(String::from(
+                "Cannot sweep with several channels",
+            )));
+        }
+        if samplerate <= 0.0 {
+            return Err(Error::InvalidSetting(String::from(
+                "Sample rate must be set before sweeping",
+            )));
+        }
+        let centers = sweep.centers(samplerate, fft_size);
+        if let Some(center) = centers
+            .iter()
+            .find(|&&c| !self.capabilities.supports_frequency(c))
+        {
+            return Err(Error::InvalidSetting(format!(
+                "Sweep step at {} out of the device range",
+                center
+            )));
+        }
+        Ok(())
+    }
+
+    /// Step the tuner across a wide range instead of staying at one frequency
+    pub fn set_sweep(&mut self, sweep: Option<Sweep>) -> Result<()> {
+        match sweep {
+            Some(sweep) => {
+                self.check_sweep(&sweep, self.samplerate(), self.fft_size)?;
+                let steps = sweep.centers(self.samplerate(), self.fft_size).len();
+                println!("Sweeping {} in {} steps", sweep, steps);
+            }
+            None if self.sweep.is_some() => {
+                self.tune(self.frequency)?;
+                println!("Sweep stopped, back to {}", self.frequency);
+            }
+            None => (),
+        }
+        self.sweep = sweep;
+        self.sweep_averagers.clear();
+        self.history_valid = false;
+        Ok(())
+    }
+
+    pub fn sweep(&self) -> Option<Sweep> {
+        self.sweep
+    }
+
+    pub fn start_recording(&mut self, path: &Path) -> Result<()> {
+        if self.sweep.is_some() {
+            return Err(Error::Unsupported(String::from(
+                "Cannot record while sweeping",
+            )));
+        }
+        self.stop_recording()?;
+        let mut recorder = Recorder::create(path, self.samplerate(), self.source.description())?;
+        recorder.add_capture(self.frequency() + self.mixer.shift);
+        self.recorder = Some(recorder);
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> Result<()> {
+        match self.recorder.take() {
+            Some(mut recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    pub fn activate(&mut self) -> Result<()> {
+        self.source.activate()?;
+        self.update_gain_status();
+        self.update_level_offset();
+        Ok(())
+    }
+
+    pub fn row_stats(&self) -> RowStats {
+        self.row_stats
+    }
+
+    pub fn statistics(&self) -> Statistics {
+        self.statistics
+    }
+
+    pub fn get_block(&mut self) -> Result<&[f32]> {
+        let mut stats = RowStats::default();
+        match self.sweep {
+            Some(sweep) => self.measure_sweep(&sweep, &mut stats)?,
+            None => self.measure(&mut stats)?,
+        }
+        self.row_stats = stats;
+        self.statistics.add(&stats);
+
+        let (first_frequency, bin_width) = self.bin_frequencies();
+        let row = match self.sweep {
+            Some(_) => &self.sweep_row,
+            None => &self.channels[0].buffer_magsq,
+        };
+        if let Some(detector) = &mut self.detector {
+            // A row without any block holds no new information
+            if stats.blocks > 0 {
+                detector.process(row, first_frequency, bin_width);
+            }
+        }
+        Ok(row)
+    }
+
+    // Step the tuner across the sweep range, keeping the flat centre of each spectrum
+    fn measure_sweep(&mut self, sweep: &Sweep, stats: &mut RowStats) -> Result<()> {
+        let samplerate = self.samplerate();
+        let centers = sweep.centers(samplerate, self.fft_size);
+        let kept = sweep.kept_bins(self.fft_size);
+        let first = (self.fft_size - kept) / 2;
+        if self.sweep_averagers.len() != centers.len() {
+            let mut averager = self.channels[0].averager.clone();
+            averager.reset();
+            self.sweep_averagers = vec![averager; centers.len()];
+        }
+        self.sweep_row.resize(centers.len() * kept, 0.0);
+
+        for (i, &center) in centers.iter().enumerate() {
+            self.tune(center)?;
+            self.discard((sweep.settle_time * samplerate) as usize, stats)?;
+            self.history_valid = false;
+
+            // Each step keeps its own exponential average or hold
+            std::mem::swap(&mut self.channels[0].averager, &mut self.sweep_averagers[i]);
+            let result = self.measure(stats);
+            std::mem::swap(&mut self.channels[0].averager, &mut self.sweep_averagers[i]);
+            result?;
+
+            self.sweep_row[i * kept..(i + 1) * kept]
+                .copy_from_slice(&self.channels[0].buffer_magsq[first..first + kept]);
+        }
+        Ok(())
+    }
+
+    // Read and drop samples, e.g. while the tuner settles after a retune
+    fn discard(&mut self, num_samples: usize, stats: &mut RowStats) -> Result<()> {
+        let mut remaining = num_samples;
+        while remaining > 0 {
+            let n = remaining.min(self.fft_size);
+            match self.source.read(&mut self.channels[0].buffer_read[..n])? {
+                ReadStatus::Ok => (),
+                // No block is lost but the row still misses samples
+                ReadStatus::Overflow => stats.overflows += 1,
+                ReadStatus::EndOfStream => return Err(Error::EndOfStream),
+            }
+            remaining -= n;
+        }
+        Ok(())
+    }
+
+    // Flag the bins overlapping the annotations of the samples just read
+    fn mark_annotations(&mut self) {
+        let (first_frequency, bin_width) = self.bin_frequencies();
+        let num_bins = self.annotated.len() as f64;
+        for &(low, high) in self.source.annotations() {
+            // Bin i spans half a bin on each side of its center
+            let start = ((low - first_frequency) / bin_width - 0.5).floor() + 1.0;
+            let end = ((high - first_frequency) / bin_width + 0.5).ceil();
+            let (start, end) = (start.clamp(0.0, num_bins), end.clamp(0.0, num_bins));
+            if start < end {
+                self.annotated[start as usize..end as usize].fill(true);
+            }
+        }
+    }
+
+    // Average the spectra of `averaging` blocks into the buffer_magsq of each channel
+    fn measure(&mut self, stats: &mut RowStats) -> Result<()> {
+        // New samples needed per block, the rest is taken from the previous one
+        let hop = ((self.fft_size as f32 * (1.0 - self.overlap)).round() as usize)
+            .clamp(1, self.fft_size);
+        let cross_spectrum = self.cross_spectrum && self.channels.len() > 1;
+        self.annotated.clear();
+        self.annotated.resize(self.fft_size, false);
+        if cross_spectrum {
+            self.cross_sum.clear();
+            self.cross_sum.resize(self.fft_size, Complex32::zero());
+        }
 
         for _ in 0..self.averaging {
-            // Read new chunk of data
-            let _num_samps = match self
-                .rx_stream
-                .read(&[&mut self.buffer_c32], 5000000) {
-                    Ok(n) => n,
-                    Err(error) => match error.code {
-                        soapysdr::ErrorCode::Overflow => continue,
-                        _ => panic!("error receiving samples: {:?}", error),
-                    }
+            // Read new chunk of data, a whole block if there is no usable history
+            let num_new = if self.history_valid {
+                hop
+            } else {
+                self.fft_size
+            };
+            let status = match self.channels.as_mut_slice() {
+                [channel] => self.source.read(&mut channel.buffer_read[..num_new])?,
+                channels => {
+                    let mut buffers: Vec<&mut [Complex32]> = channels
+                        .iter_mut()
+                        .map(|channel| &mut channel.buffer_read[..num_new])
+                        .collect();
+                    self.source.read_channels(&mut buffers)?
+                }
             };
+            match status {
+                ReadStatus::Ok => (),
+                ReadStatus::Overflow => {
+                    // Samples are no longer contiguous with the history, the
+                    // block is left out and the averaging divides by fewer blocks
+                    stats.overflows += 1;
+                    stats.dropped_blocks += 1;
+                    self.history_valid = false;
+                    continue;
+                }
+                // Nothing more will come, rather than an endless run of gap rows
+                ReadStatus::EndOfStream => return Err(Error::EndOfStream),
+            }
 
-            // Compute in-place FFT with scratch memory to avoid allocations
-            self.fft
-                .process_with_scratch(&mut self.buffer_c32, &mut self.scratch);
+            self.mark_annotations();
 
-            // Convert FFT bins to squared magnitude and add to averaging buffer
-            for i in 0..NUM_SAMPLES {
-                self.buffer_magsq[i] += self.buffer_c32[i].norm_sqr();
+            // The recording keeps the samples as received, corrections only affect the display
+            if let Some(recorder) = &mut self.recorder {
+                if let Err(error) = recorder.write(&self.channels[0].buffer_read[..num_new]) {
+                    // Only the recording is lost, the display goes on
+                    self.recorder = None;
+                    return Err(error);
+                }
             }
+
+            for channel in self.channels.iter_mut() {
+                let new_samples = &mut channel.buffer_read[..num_new];
+                // Imbalances belong to the receiver, correct them before shifting
+                if self.dc_mode == DcMode::Mean {
+                    channel.dc_blocker.apply(new_samples);
+                }
+                if self.iq_correction {
+                    channel.iq_corrector.apply(new_samples);
+                }
+                self.mixer.apply(new_samples);
+            }
+            self.mixer.advance(num_new);
+
+            // The spike sits at the receiver's DC, moved by the mixer shift if any
+            let half = self.fft_size / 2;
+            let offset = (self.mixer.shift / self.samplerate() * self.fft_size as f64).round();
+            let spike = half as i64 + offset as i64;
+            for channel in self.channels.iter_mut() {
+                channel.transform(num_new, &self.window, &*self.fft, &mut self.scratch);
+                if self.dc_mode == DcMode::Interpolate && (0..self.fft_size as i64).contains(&spike)
+                {
+                    interpolate_bins(&mut channel.buffer_magsq, spike as usize, DC_HALF_WIDTH);
+                }
+                channel.averager.add(&channel.buffer_magsq);
+            }
+            self.history_valid = true;
+
+            if cross_spectrum {
+                let (first, second) = (&self.channels[0], &self.channels[1]);
+                for (i, (a, b)) in first.buffer_c32.iter().zip(&second.buffer_c32).enumerate() {
+                    self.cross_sum[(i + half) % self.fft_size] += a * b.conj();
+                }
+            }
+            stats.blocks += 1;
         }
 
-        // Scale due to averaging
-        self.buffer_magsq
-            .iter_mut()
-            .for_each(|x| *x *= self.averaging_inv as f32);
+        // Scale to dBFS, a full scale tone reads 0 dB whatever the FFT size and
+        // window, then to dBm if calibrated
+        let fft_gain = (self.fft_size * self.fft_size) as f64;
+        let calibration = 10f64.powf(self.level_offset.unwrap_or(0.0) / 10.0);
+        let scale =
+            (self.window.power_correction(self.window_scaling) / fft_gain * calibration) as f32;
+        for channel in self.channels.iter_mut() {
+            channel
+                .buffer_magsq
+                .copy_from_slice(channel.averager.finish());
+            channel.buffer_magsq.iter_mut().for_each(|x| *x *= scale);
+        }
+        if cross_spectrum {
+            self.phase_difference.clear();
+            self.phase_difference
+                .extend(self.cross_sum.iter().map(|x| x.arg()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Bin width of 1 kHz, tones on multiples of it fall right on a bin
+    const FFT_SIZE: usize = 1024;
+    const SAMPLERATE: f64 = 1.024e6;
+
+    // Tunable source on top of the generator, whose signals are offsets from `base`
+    struct TestSource {
+        generator: GeneratorSource,
+        // Frequency at which the generator signals are centred
+        base: f64,
+        // Frequency currently tuned to
+        tuned: f64,
+        // Phase of the shift from `base` to `tuned`, in radians
+        phase: f64,
+        // Index of the read reporting an overflow, if any
+        overflow_at: Option<usize>,
+        // Reads so far
+        reads: usize,
+        // Ranges reported as annotations
+        annotations: Vec<(f64, f64)>,
+        // Frequency ranges supported, any if empty
+        frequency_ranges: Vec<(f64, f64)>,
+    }
+
+    impl TestSource {
+        fn new(signals: Vec<Signal>, base: f64) -> Self {
+            Self {
+                generator: GeneratorSource::new(signals, 1, false),
+                base,
+                tuned: base,
+                phase: 0.0,
+                overflow_at: None,
+                reads: 0,
+                annotations: Vec::new(),
+                frequency_ranges: Vec::new(),
+            }
+        }
+    }
+
+    impl SampleSource for TestSource {
+        fn read(&mut self, buffer: &mut [Complex32]) -> Result<ReadStatus> {
+            self.generator.read(buffer)?;
+            // Like the anti-aliasing filter of a tuner, nothing from outside the band
+            if (self.base - self.tuned).abs() >= SAMPLERATE / 2.0 {
+                buffer.fill(Complex32::zero());
+            }
+            let step = TAU * (self.base - self.tuned) / SAMPLERATE;
+            for sample in buffer.iter_mut() {
+                *sample *= Complex32::from_polar(1.0, self.phase as f32);
+                self.phase = (self.phase + step) % TAU;
+            }
+            self.reads += 1;
+            if self.overflow_at == Some(self.reads) {
+                return Ok(ReadStatus::Overflow);
+            }
+            Ok(ReadStatus::Ok)
+        }
+
+        fn set_frequency(&mut self, frequency: f64) -> Result<()> {
+            self.tuned = frequency;
+            Ok(())
+        }
+
+        fn set_samplerate(&mut self, samplerate: f64) -> Result<()> {
+            self.generator.set_samplerate(samplerate)
+        }
+
+        fn set_bandwidth(&mut self, _bandwidth: f64) -> Result<()> {
+            Ok(())
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            Capabilities {
+                tunable: true,
+                frequency_ranges: self.frequency_ranges.clone(),
+                ..Capabilities::default()
+            }
+        }
+
+        fn description(&self) -> String {
+            String::from("test source")
+        }
+
+        fn annotations(&self) -> &[(f64, f64)] {
+            &self.annotations
+        }
+    }
+
+    fn supplier(source: TestSource, averaging: u32) -> DataSupplier {
+        let mut supplier = DataSupplier::new(
+            Box::new(source),
+            averaging,
+            AveragingMode::Mean,
+            0.5,
+            FFT_SIZE,
+            WindowFunction::Hann,
+            WindowScaling::Amplitude,
+        )
+        .unwrap();
+        supplier.set_samplerate(SAMPLERATE).unwrap();
+        supplier.set_frequency(100e6).unwrap();
+        supplier
+    }
+
+    fn full_scale_tone(frequency: f64) -> Signal {
+        Signal::Tone {
+            frequency,
+            power: 0.0,
+        }
+    }
+
+    fn peak(row: &[f32]) -> (usize, f32) {
+        row.iter()
+            .copied()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap()
+    }
+
+    #[test]
+    fn full_scale_tone_reads_0_dbfs_in_its_bin() {
+        let mut supplier = supplier(TestSource::new(vec![full_scale_tone(100e3)], 100e6), 4);
+        let (index, power) = peak(supplier.get_block().unwrap());
+        let (first, bin_width) = supplier.bin_frequencies();
+        assert_eq!(first + index as f64 * bin_width, 100.1e6);
+        assert!(
+            (10.0 * power.log10()).abs() < 0.1,
+            "{} dBFS",
+            10.0 * power.log10()
+        );
+    }
+
+    #[test]
+    fn counts_overflows_in_the_row_stats() {
+        let mut source = TestSource::new(vec![full_scale_tone(0.0)], 100e6);
+        source.overflow_at = Some(2);
+        let mut supplier = supplier(source, 4);
+        supplier.get_block().unwrap();
+        let stats = supplier.row_stats();
+        assert_eq!(stats.overflows, 1);
+        assert_eq!(stats.dropped_blocks, 1);
+        assert_eq!(stats.blocks, 3);
+    }
+
+    #[test]
+    fn stitches_sweep_steps_side_by_side() {
+        let source = TestSource::new(vec![full_scale_tone(0.0)], 105e6);
+        let mut supplier = supplier(source, 1);
+        supplier.set_sweep(Some(sweep())).unwrap();
+        let row = supplier.get_block().unwrap().to_vec();
+        // 14 steps of 768 bins cover the 10 MHz
+        assert_eq!(row.len(), 14 * 768);
+        let (first, bin_width) = supplier.bin_frequencies();
+        assert_eq!((first, bin_width), (100e6, 1e3));
+        let (index, power) = peak(&row);
+        assert_eq!(first + index as f64 * bin_width, 105e6);
+        assert!(
+            (10.0 * power.log10()).abs() < 0.1,
+            "{} dBFS",
+            10.0 * power.log10()
+        );
+    }
+
+    fn sweep() -> Sweep {
+        Sweep {
+            start: 100e6,
+            stop: 110e6,
+            keep: 0.75,
+            settle_time: 0.0,
+        }
+    }
+
+    #[test]
+    fn counts_overflows_while_settling_in_the_row_stats() {
+        let mut source = TestSource::new(vec![full_scale_tone(0.0)], 105e6);
+        source.overflow_at = Some(1);
+        let mut supplier = supplier(source, 1);
+        supplier
+            .set_sweep(Some(Sweep {
+                settle_time: 1e-3,
+                ..sweep()
+            }))
+            .unwrap();
+        supplier.get_block().unwrap();
+        let stats = supplier.row_stats();
+        assert_eq!(stats.overflows, 1);
+        assert_eq!(stats.dropped_blocks, 0);
+        assert_eq!(stats.blocks, 14);
+    }
+
+    #[test]
+    fn refuses_settings_that_break_the_sweep() {
+        let mut source = TestSource::new(Vec::new(), 100e6);
+        source.frequency_ranges = vec![(1e6, 110.4e6)];
+        let mut supplier = supplier(source, 1);
+        supplier.set_sweep(Some(sweep())).unwrap();
+        // Wider steps would tune the last one above the device range
+        assert!(supplier.set_samplerate(4.096e6).is_err());
+        assert_eq!(supplier.samplerate(), SAMPLERATE);
+        assert_eq!(supplier.sweep(), Some(sweep()));
+        supplier.set_fft_size(4096).unwrap();
+        assert_eq!(supplier.get_block().unwrap().len(), 14 * 3072);
+    }
 
-        &self.buffer_magsq
+    #[test]
+    fn flags_the_bins_covered_by_annotations() {
+        let mut source = TestSource::new(vec![full_scale_tone(0.0)], 100e6);
+        source.annotations = vec![(100.2e6, 100.3e6)];
+        let mut supplier = supplier(source, 1);
+        supplier.get_block().unwrap();
+        let (first, bin_width) = supplier.bin_frequencies();
+        let flagged: Vec<f64> = supplier
+            .annotated_bins()
+            .iter()
+            .enumerate()
+            .filter(|(_, &annotated)| annotated)
+            .map(|(i, _)| first + i as f64 * bin_width)
+            .collect();
+        assert_eq!(flagged.len(), 101);
+        assert_eq!(flagged[0], 100.2e6);
+        assert_eq!(flagged[100], 100.3e6);
     }
 }
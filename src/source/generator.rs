@@ -0,0 +1,380 @@
+use super::{Capabilities, ReadStatus, SampleSource, Throttle};
+use crate::error;
+use num::complex::Complex32;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Normal};
+use std::f64::consts::TAU;
+use std::str::FromStr;
+
+/// Component of a synthetic scenario. Frequencies are offsets from the center
+/// frequency in Hz and powers are given in dB relative to full scale.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Signal {
+    /// `noise:<power>` white gaussian noise floor
+    Noise { power: f64 },
+    /// `tone:<freq>:<power>` continuous wave carrier
+    Tone { frequency: f64, power: f64 },
+    /// `am:<freq>:<power>:<mod_freq>:<depth>` amplitude modulated carrier
+    Am {
+        frequency: f64,
+        power: f64,
+        mod_frequency: f64,
+        depth: f64,
+    },
+    /// `fm:<freq>:<power>:<mod_freq>:<deviation>` frequency modulated carrier
+    Fm {
+        frequency: f64,
+        power: f64,
+        mod_frequency: f64,
+        deviation: f64,
+    },
+    /// `chirp:<start>:<stop>:<period>:<power>` repeating linear sweep
+    Chirp {
+        start: f64,
+        stop: f64,
+        period: f64,
+        power: f64,
+    },
+    /// `hop:<span>:<channels>:<dwell>:<power>` bursts hopping between channels
+    Hop {
+        span: f64,
+        channels: u32,
+        dwell: f64,
+        power: f64,
+    },
+}
+
+impl FromStr for Signal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split(':');
+        let kind = fields.next().unwrap_or_default();
+        let params = fields
+            .map(|f| {
+                f.parse::<f64>()
+                    .ok()
+                    .filter(|x| x.is_finite())
+                    .ok_or_else(|| format!("invalid number {:?}", f))
+            })
+            .collect::<Result<Vec<f64>, String>>()?;
+        let expect = |count: usize| {
+            if params.len() == count {
+                Ok(())
+            } else {
+                Err(format!("{} takes {} parameters", kind, count))
+            }
+        };
+
+        match kind {
+            "noise" => {
+                expect(1)?;
+                Ok(Signal::Noise { power: params[0] })
+            }
+            "tone" => {
+                expect(2)?;
+                Ok(Signal::Tone {
+                    frequency: params[0],
+                    power: params[1],
+                })
+            }
+            "am" => {
+                expect(4)?;
+                Ok(Signal::Am {
+                    frequency: params[0],
+                    power: params[1],
+                    mod_frequency: params[2],
+                    depth: params[3],
+                })
+            }
+            "fm" => {
+                expect(4)?;
+                Ok(Signal::Fm {
+                    frequency: params[0],
+                    power: params[1],
+                    mod_frequency: params[2],
+                    deviation: params[3],
+                })
+            }
+            "chirp" => {
+                expect(4)?;
+                Ok(Signal::Chirp {
+                    start: params[0],
+                    stop: params[1],
+                    period: params[2],
+                    power: params[3],
+                })
+            }
+            "hop" => {
+                expect(4)?;
+                Ok(Signal::Hop {
+                    span: params[0],
+                    channels: params[1].max(1.0) as u32,
+                    dwell: params[2],
+                    power: params[3],
+                })
+            }
+            _ => Err(format!("unknown signal type {:?}", kind)),
+        }
+    }
+}
+
+/// Scenario used when no signal is given explicitly
+pub fn demo_scenario() -> Vec<Signal> {
+    vec![
+        Signal::Noise { power: -60.0 },
+        Signal::Tone {
+            frequency: 150e3,
+            power: -20.0,
+        },
+        Signal::Am {
+            frequency: -300e3,
+            power: -30.0,
+            mod_frequency: 1e3,
+            depth: 0.8,
+        },
+        Signal::Fm {
+            frequency: 400e3,
+            power: -25.0,
+            mod_frequency: 2.0,
+            deviation: 50e3,
+        },
+        Signal::Chirp {
+            start: -800e3,
+            stop: -500e3,
+            period: 0.5,
+            power: -35.0,
+        },
+        Signal::Hop {
+            span: 400e3,
+            channels: 16,
+            dwell: 0.05,
+            power: -30.0,
+        },
+    ]
+}
+
+// Running state of one signal
+struct Oscillator {
+    signal: Signal,
+    // Carrier phase in radians
+    phase: f64,
+    // Current hopping channel offset
+    hop_frequency: f64,
+    // Distribution of each of I and Q for noise signals
+    noise: Option<Normal<f64>>,
+}
+
+/// Synthetic scenario generator, deterministic for a given seed
+pub struct GeneratorSource {
+    // Signals being generated
+    oscillators: Vec<Oscillator>,
+    // Random source for noise and hopping patterns
+    rng: StdRng,
+    // Sample rate of the generated signal
+    samplerate: f64,
+    // Index of the next generated sample
+    sample_index: u64,
+    // Paces generation at the configured sample rate, if enabled
+    throttle: Option<Throttle>,
+}
+
+impl GeneratorSource {
+    pub fn new(signals: Vec<Signal>, seed: u64, throttle: bool) -> Self {
+        let oscillators = signals
+            .into_iter()
+            .map(|signal| {
+                let noise = match signal {
+                    // Half the power goes to each of I and Q
+                    Signal::Noise { power } => {
+                        Normal::new(0.0, (10f64.powf(power / 10.0) / 2.0).sqrt()).ok()
+                    }
+                    _ => None,
+                };
+                Oscillator {
+                    signal,
+                    phase: 0.0,
+                    hop_frequency: 0.0,
+                    noise,
+                }
+            })
+            .collect();
+
+        Self {
+            oscillators,
+            rng: StdRng::seed_from_u64(seed),
+            samplerate: 0.0,
+            sample_index: 0,
+            throttle: throttle.then(Throttle::new),
+        }
+    }
+}
+
+fn amplitude(power: f64) -> f64 {
+    10f64.powf(power / 20.0)
+}
+
+impl SampleSource for GeneratorSource {
+    fn read(&mut self, buffer: &mut [Complex32]) -> error::Result<ReadStatus> {
+        if self.samplerate <= 0.0 {
+            buffer.iter_mut().for_each(|x| *x = Complex32::default());
+            return Ok(ReadStatus::Ok);
+        }
+        let dt = 1.0 / self.samplerate;
+
+        for sample in buffer.iter_mut() {
+            let t = self.sample_index as f64 * dt;
+            let mut value = (0.0f64, 0.0f64);
+
+            for osc in self.oscillators.iter_mut() {
+                let (frequency, envelope) = match osc.signal {
+                    Signal::Noise { .. } => {
+                        if let Some(normal) = &osc.noise {
+                            value.0 += normal.sample(&mut self.rng);
+                            value.1 += normal.sample(&mut self.rng);
+                        }
+                        continue;
+                    }
+                    Signal::Tone { frequency, power } => (frequency, amplitude(power)),
+                    Signal::Am {
+                        frequency,
+                        power,
+                        mod_frequency,
+                        depth,
+                    } => (
+                        frequency,
+                        amplitude(power) * (1.0 + depth * (TAU * mod_frequency * t).cos()),
+                    ),
+                    Signal::Fm {
+                        frequency,
+                        power,
+                        mod_frequency,
+                        deviation,
+                    } => (
+                        frequency + deviation * (TAU * mod_frequency * t).sin(),
+                        amplitude(power),
+                    ),
+                    Signal::Chirp {
+                        start,
+                        stop,
+                        period,
+                        power,
+                    } => (
+                        start + (stop - start) * (t % period) / period,
+                        amplitude(power),
+                    ),
+                    Signal::Hop {
+                        span,
+                        channels,
+                        dwell,
+                        power,
+                    } => {
+                        let dwell_samples = ((dwell * self.samplerate) as u64).max(1);
+                        let slot_position = self.sample_index % dwell_samples;
+                        if slot_position == 0 {
+                            let channel = self.rng.gen_range(0..channels);
+                            osc.hop_frequency =
+                                span * ((channel as f64 + 0.5) / channels as f64 - 0.5);
+                        }
+                        // Bursts leave a quiet gap at the end of each dwell period
+                        let on = slot_position < dwell_samples * 3 / 4;
+                        (osc.hop_frequency, if on { amplitude(power) } else { 0.0 })
+                    }
+                };
+
+                value.0 += envelope * osc.phase.cos();
+                value.1 += envelope * osc.phase.sin();
+                osc.phase = (osc.phase + TAU * frequency * dt) % TAU;
+            }
+
+            *sample = Complex32::new(value.0 as f32, value.1 as f32);
+            self.sample_index += 1;
+        }
+
+        if let Some(throttle) = &mut self.throttle {
+            throttle.wait(buffer.len());
+        }
+        Ok(ReadStatus::Ok)
+    }
+
+    fn set_frequency(&mut self, _frequency: f64) -> error::Result<()> {
+        Ok(())
+    }
+
+    fn set_samplerate(&mut self, samplerate: f64) -> error::Result<()> {
+        self.samplerate = samplerate;
+        if let Some(throttle) = &mut self.throttle {
+            throttle.set_samplerate(samplerate);
+        }
+        Ok(())
+    }
+
+    fn set_bandwidth(&mut self, _bandwidth: f64) -> error::Result<()> {
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    fn description(&self) -> String {
+        format!("signal generator ({} signals)", self.oscillators.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate(signals: &[&str], seed: u64, len: usize) -> Vec<Complex32> {
+        let signals = signals.iter().map(|s| s.parse().unwrap()).collect();
+        let mut source = GeneratorSource::new(signals, seed, false);
+        source.set_samplerate(1e6).unwrap();
+        let mut buffer = vec![Complex32::default(); len];
+        assert_eq!(source.read(&mut buffer).unwrap(), ReadStatus::Ok);
+        buffer
+    }
+
+    fn mean_power(samples: &[Complex32]) -> f64 {
+        samples.iter().map(|x| x.norm_sqr() as f64).sum::<f64>() / samples.len() as f64
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_samples() {
+        let signals = ["noise:-20", "hop:400e3:16:0.001:-10"];
+        assert_eq!(generate(&signals, 7, 4096), generate(&signals, 7, 4096));
+        assert_ne!(generate(&signals, 7, 4096), generate(&signals, 8, 4096));
+    }
+
+    #[test]
+    fn signals_have_the_requested_power() {
+        let tone = mean_power(&generate(&["tone:1e3:-10"], 1, 10000));
+        assert!((tone / 0.1 - 1.0).abs() < 1e-3, "{}", tone);
+        let noise = mean_power(&generate(&["noise:-20"], 1, 100000));
+        assert!((noise / 0.01 - 1.0).abs() < 0.02, "{}", noise);
+    }
+
+    #[test]
+    fn rejects_malformed_specs() {
+        for spec in [
+            "noise:nan",
+            "noise:inf",
+            "tone:1e3",
+            "tone:1e3:-10:5",
+            "tone:abc:-10",
+            "am:-inf:-10:1e3:0.5",
+            "square:1e3:-10",
+            "",
+        ] {
+            assert!(spec.parse::<Signal>().is_err(), "{:?}", spec);
+        }
+        assert_eq!(
+            "tone:-2.5e3:-30".parse(),
+            Ok(Signal::Tone {
+                frequency: -2.5e3,
+                power: -30.0
+            })
+        );
+    }
+}
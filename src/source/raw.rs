@@ -0,0 +1,210 @@
+use super::{Capabilities, ReadStatus, SampleSource, Throttle};
+use crate::error::{io, Result};
+use num::complex::Complex32;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+
+/// Encoding of interleaved I/Q samples in a raw stream
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleFormat {
+    /// Unsigned 8 bit (rtl_sdr)
+    Cu8,
+    /// Signed 8 bit (hackrf_transfer)
+    Cs8,
+    /// Signed 16 bit little endian
+    Cs16,
+    /// 32 bit float little endian (GNU Radio)
+    Cf32,
+}
+
+impl SampleFormat {
+    /// Size in bytes of one complex sample
+    pub fn sample_size(&self) -> usize {
+        match self {
+            SampleFormat::Cu8 | SampleFormat::Cs8 => 2,
+            SampleFormat::Cs16 => 4,
+            SampleFormat::Cf32 => 8,
+        }
+    }
+
+    /// Convert raw bytes into complex samples normalized to [-1, 1]
+    pub fn convert(&self, bytes: &[u8], samples: &mut [Complex32]) {
+        let chunks = bytes.chunks_exact(self.sample_size());
+        for (sample, chunk) in samples.iter_mut().zip(chunks) {
+            *sample = match self {
+                SampleFormat::Cu8 => Complex32::new(
+                    (chunk[0] as f32 - 127.5) / 128.0,
+                    (chunk[1] as f32 - 127.5) / 128.0,
+                ),
+                SampleFormat::Cs8 => {
+                    Complex32::new(chunk[0] as i8 as f32 / 128.0, chunk[1] as i8 as f32 / 128.0)
+                }
+                SampleFormat::Cs16 => Complex32::new(
+                    i16::from_le_bytes([chunk[0], chunk[1]]) as f32 / 32768.0,
+                    i16::from_le_bytes([chunk[2], chunk[3]]) as f32 / 32768.0,
+                ),
+                SampleFormat::Cf32 => Complex32::new(
+                    f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]),
+                    f32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]),
+                ),
+            };
+        }
+    }
+}
+
+/// Interleaved I/Q samples from a byte stream, such as a capture file
+pub struct RawSource {
+    // Input byte stream
+    reader: Box<dyn Read + Send>,
+    // Path of the input file, used to start over when repeating
+    path: Option<PathBuf>,
+    // Encoding of the input samples
+    format: SampleFormat,
+    // Restart from the beginning of the file when reaching its end
+    repeat: bool,
+    // Paces delivery at the recorded sample rate, if enabled
+    throttle: Option<Throttle>,
+    // Buffer for raw input bytes
+    bytes: Vec<u8>,
+}
+
+impl RawSource {
+    pub fn new(reader: Box<dyn Read + Send>, format: SampleFormat, throttle: bool) -> Self {
+        Self {
+            reader,
+            path: None,
+            format,
+            repeat: false,
+            throttle: throttle.then(Throttle::new),
+            bytes: Vec::new(),
+        }
+    }
+
+    pub fn stdin(format: SampleFormat, throttle: bool) -> Self {
+        Self::new(Box::new(std::io::stdin()), format, throttle)
+    }
+
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        format: SampleFormat,
+        throttle: bool,
+        repeat: bool,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).map_err(io(format!("Cannot open input file {}", path.display())))?;
+        let mut source = Self::new(Box::new(BufReader::new(file)), format, throttle);
+        source.path = Some(path.to_path_buf());
+        source.repeat = repeat;
+        Ok(source)
+    }
+
+    // Fill the byte buffer, returns false if the stream ended before
+    fn fill_bytes(&mut self) -> Result<bool> {
+        let mut filled = 0;
+        while filled < self.bytes.len() {
+            match self.reader.read(&mut self.bytes[filled..]) {
+                Ok(0) => match (&self.path, self.repeat) {
+                    (Some(path), true) => {
+                        let file = File::open(path)
+                            .map_err(io(format!("Cannot reopen input file {}", path.display())))?;
+                        self.reader = Box::new(BufReader::new(file));
+                    }
+                    _ => return Ok(false),
+                },
+                Ok(n) => filled += n,
+                Err(error) if error.kind() == ErrorKind::Interrupted => (),
+                Err(error) => return Err(io("Cannot read samples")(error)),
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl SampleSource for RawSource {
+    fn read(&mut self, buffer: &mut [Complex32]) -> Result<ReadStatus> {
+        self.bytes
+            .resize(buffer.len() * self.format.sample_size(), 0);
+        if !self.fill_bytes()? {
+            return Ok(ReadStatus::EndOfStream);
+        }
+        self.format.convert(&self.bytes, buffer);
+        if let Some(throttle) = &mut self.throttle {
+            throttle.wait(buffer.len());
+        }
+        Ok(ReadStatus::Ok)
+    }
+
+    fn set_frequency(&mut self, _frequency: f64) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_samplerate(&mut self, samplerate: f64) -> Result<()> {
+        if let Some(throttle) = &mut self.throttle {
+            throttle.set_samplerate(samplerate);
+        }
+        Ok(())
+    }
+
+    fn set_bandwidth(&mut self, _bandwidth: f64) -> Result<()> {
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    fn description(&self) -> String {
+        match &self.path {
+            Some(path) => format!("{} ({:?})", path.display(), self.format),
+            None => format!("standard input ({:?})", self.format),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn convert(format: SampleFormat, bytes: &[u8]) -> Vec<Complex32> {
+        let mut samples = vec![Complex32::default(); bytes.len() / format.sample_size()];
+        format.convert(bytes, &mut samples);
+        samples
+    }
+
+    #[test]
+    fn converts_each_format_to_full_scale() {
+        assert_eq!(
+            convert(SampleFormat::Cu8, &[255, 0, 127, 128]),
+            [
+                Complex32::new(0.99609375, -0.99609375),
+                Complex32::new(-0.00390625, 0.00390625),
+            ]
+        );
+        assert_eq!(
+            convert(SampleFormat::Cs8, &[0x7f, 0x80, 0, 0x40]),
+            [Complex32::new(0.9921875, -1.0), Complex32::new(0.0, 0.5),]
+        );
+        assert_eq!(
+            convert(SampleFormat::Cs16, &[0xff, 0x7f, 0x00, 0x80]),
+            [Complex32::new(0.9999695, -1.0)]
+        );
+        let mut bytes = 0.25f32.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&(-1.5f32).to_le_bytes());
+        assert_eq!(
+            convert(SampleFormat::Cf32, &bytes),
+            [Complex32::new(0.25, -1.5)]
+        );
+    }
+
+    #[test]
+    fn reports_end_of_stream_on_a_partial_block() {
+        let bytes = vec![128u8; 6];
+        let mut source = RawSource::new(Box::new(Cursor::new(bytes)), SampleFormat::Cu8, false);
+        let mut buffer = [Complex32::default(); 2];
+        assert_eq!(source.read(&mut buffer).unwrap(), ReadStatus::Ok);
+        assert_eq!(source.read(&mut buffer).unwrap(), ReadStatus::EndOfStream);
+    }
+}
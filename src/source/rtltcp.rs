@@ -0,0 +1,317 @@
+use super::{Capabilities, GainRange, ReadStatus, SampleFormat, SampleSource};
+use crate::error::{io, Error, Result};
+use num::complex::Complex32;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+// Command identifiers of the rtl_tcp protocol
+const CMD_SET_FREQUENCY: u8 = 0x01;
+const CMD_SET_SAMPLE_RATE: u8 = 0x02;
+const CMD_SET_GAIN_MODE: u8 = 0x03;
+const CMD_SET_GAIN: u8 = 0x04;
+const CMD_SET_FREQ_CORRECTION: u8 = 0x05;
+// Longest wait for samples, so that a stalled server doesn't block stopping
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+// Bytes per cu8 sample
+const SAMPLE_SIZE: usize = 2;
+
+/// Tuner name and approximate frequency range for an rtl_tcp tuner type
+fn tuner_info(tuner_type: u32) -> (&'static str, Vec<(f64, f64)>) {
+    match tuner_type {
+        1 => ("E4000", vec![(52e6, 2200e6)]),
+        2 => ("FC0012", vec![(22e6, 948.6e6)]),
+        3 => ("FC0013", vec![(22e6, 1100e6)]),
+        4 => ("FC2580", vec![(146e6, 308e6), (438e6, 924e6)]),
+        5 => ("R820T", vec![(24e6, 1766e6)]),
+        6 => ("R828D", vec![(24e6, 1766e6)]),
+        _ => ("unknown", Vec::new()),
+    }
+}
+
+// Argument of a command, which the protocol limits to 32 bits
+fn command_argument(value: f64, name: &str) -> Result<u32> {
+    let rounded = value.round();
+    if rounded >= 0.0 && rounded <= u32::MAX as f64 {
+        Ok(rounded as u32)
+    } else {
+        Err(Error::InvalidSetting(format!(
+            "{} {} out of the rtl_tcp range",
+            name, value
+        )))
+    }
+}
+
+/// Client for a remote receiver served by `rtl_tcp`
+pub struct RtlTcpSource {
+    // Incoming sample stream
+    reader: BufReader<TcpStream>,
+    // Outgoing command stream, sharing the same connection
+    writer: TcpStream,
+    // Address of the server
+    address: String,
+    // Tuner type reported by the server
+    tuner_type: u32,
+    // Last manual gain requested, the server does not report it back
+    gain: Option<f64>,
+    // Offset of the tuner from the requested frequency, rtl_tcp has no way to compensate it
+    lo_offset: f64,
+    // Frequency the tuner was last set to
+    tuned_frequency: Option<f64>,
+    // Bytes of a sample already received when a read timed out
+    partial_sample: usize,
+    // Buffer for raw u8 samples
+    bytes: Vec<u8>,
+}
+
+impl RtlTcpSource {
+    pub fn connect(address: &str) -> Result<Self> {
+        let stream = TcpStream::connect(address).map_err(io("Cannot connect to rtl_tcp server"))?;
+        stream.set_nodelay(true).ok();
+        stream
+            .set_read_timeout(Some(READ_TIMEOUT))
+            .map_err(io("Cannot set rtl_tcp read timeout"))?;
+        let writer = stream
+            .try_clone()
+            .map_err(io("Cannot clone rtl_tcp connection"))?;
+        let mut reader = BufReader::new(stream);
+
+        // Dongle information: magic, tuner type and number of gain steps
+        let mut header = [0u8; 12];
+        reader
+            .read_exact(&mut header)
+            .map_err(io("Cannot read rtl_tcp header"))?;
+        if &header[0..4] != b"RTL0" {
+            return Err(Error::Protocol(format!(
+                "{} is not an rtl_tcp server",
+                address
+            )));
+        }
+        let tuner_type = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        let gain_count = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+        println!(
+            "Connected to rtl_tcp at {}: {} tuner, {} gain steps",
+            address,
+            tuner_info(tuner_type).0,
+            gain_count
+        );
+
+        Ok(Self {
+            reader,
+            writer,
+            address: String::from(address),
+            tuner_type,
+            gain: None,
+            lo_offset: 0.0,
+            tuned_frequency: None,
+            partial_sample: 0,
+            bytes: Vec::new(),
+        })
+    }
+
+    fn send_command(&mut self, command: u8, param: u32) -> Result<()> {
+        let mut packet = [0u8; 5];
+        packet[0] = command;
+        packet[1..].copy_from_slice(&param.to_be_bytes());
+        self.writer
+            .write_all(&packet)
+            .map_err(io("Cannot send rtl_tcp command"))
+    }
+
+    // Receive `len` bytes into the byte buffer, the bytes are lost if the server stalls
+    fn receive(&mut self, len: usize) -> Result<ReadStatus> {
+        self.bytes.resize(len, 0);
+        let mut filled = 0;
+        let status = loop {
+            if filled == len {
+                break ReadStatus::Ok;
+            }
+            match self.reader.read(&mut self.bytes[filled..len]) {
+                Ok(0) => break ReadStatus::EndOfStream,
+                Ok(n) => filled += n,
+                Err(error) if error.kind() == ErrorKind::Interrupted => (),
+                Err(error)
+                    if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                {
+                    break ReadStatus::Overflow
+                }
+                Err(error) => return Err(io("Cannot receive samples")(error)),
+            }
+        };
+        self.partial_sample = (self.partial_sample + filled) % SAMPLE_SIZE;
+        Ok(status)
+    }
+}
+
+impl SampleSource for RtlTcpSource {
+    fn read(&mut self, buffer: &mut [Complex32]) -> Result<ReadStatus> {
+        // Skip the rest of a sample cut by a timeout, so that I and Q don't swap
+        if self.partial_sample > 0 {
+            let status = self.receive(SAMPLE_SIZE - self.partial_sample)?;
+            if status != ReadStatus::Ok {
+                return Ok(status);
+            }
+        }
+        let status = self.receive(buffer.len() * SAMPLE_SIZE)?;
+        if status == ReadStatus::Ok {
+            SampleFormat::Cu8.convert(&self.bytes, buffer);
+        }
+        Ok(status)
+    }
+
+    fn set_frequency(&mut self, frequency: f64) -> Result<()> {
+        let tuned = frequency + self.lo_offset;
+        self.send_command(CMD_SET_FREQUENCY, command_argument(tuned, "Frequency")?)?;
+        self.tuned_frequency = Some(tuned);
+        Ok(())
+    }
+
+    fn set_lo_offset(&mut self, offset: f64) -> Result<()> {
+        self.lo_offset = offset;
+        Ok(())
+    }
+
+    fn tuned_frequency(&self) -> Option<f64> {
+        self.tuned_frequency
+    }
+
+    fn set_samplerate(&mut self, samplerate: f64) -> Result<()> {
+        self.send_command(
+            CMD_SET_SAMPLE_RATE,
+            command_argument(samplerate, "Sample rate")?,
+        )
+    }
+
+    fn set_bandwidth(&mut self, _bandwidth: f64) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_gain(&mut self, gain: Option<f64>) -> Result<()> {
+        match gain {
+            Some(gain) => {
+                self.send_command(CMD_SET_GAIN_MODE, 1)?;
+                // Gain is given in tenths of dB
+                self.send_command(CMD_SET_GAIN, (gain * 10.0).round() as i32 as u32)?;
+            }
+            None => self.send_command(CMD_SET_GAIN_MODE, 0)?,
+        }
+        self.gain = gain;
+        Ok(())
+    }
+
+    fn gain(&self) -> Option<f64> {
+        self.gain
+    }
+
+    fn set_ppm(&mut self, ppm: f64) -> Result<f64> {
+        // The server only takes whole ppm
+        let applied = ppm.round();
+        self.send_command(CMD_SET_FREQ_CORRECTION, applied as i32 as u32)?;
+        Ok(applied)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            tunable: true,
+            frequency_ranges: tuner_info(self.tuner_type).1,
+            // The server picks the closest gain supported by the tuner
+            gain: Some(GainRange {
+                min: 0.0,
+                max: 49.6,
+                step: 0.1,
+            }),
+            gain_elements: Vec::new(),
+            agc: true,
+        }
+    }
+
+    fn description(&self) -> String {
+        format!(
+            "rtl_tcp {} ({} tuner)",
+            self.address,
+            tuner_info(self.tuner_type).0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn reads_samples_and_sends_commands() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // R820T tuner with 29 gain steps, then two cu8 samples
+            let mut header = b"RTL0".to_vec();
+            header.extend_from_slice(&5u32.to_be_bytes());
+            header.extend_from_slice(&29u32.to_be_bytes());
+            stream.write_all(&header).unwrap();
+            stream.write_all(&[255, 0, 127, 128]).unwrap();
+            let mut commands = [0u8; 20];
+            stream.read_exact(&mut commands).unwrap();
+            commands
+        });
+
+        let mut source = RtlTcpSource::connect(&address).unwrap();
+        assert_eq!(source.tuner_type, 5);
+        let mut buffer = [Complex32::default(); 2];
+        assert_eq!(source.read(&mut buffer).unwrap(), ReadStatus::Ok);
+        assert_eq!(
+            buffer,
+            [
+                Complex32::new(0.99609375, -0.99609375),
+                Complex32::new(-0.00390625, 0.00390625),
+            ]
+        );
+
+        // Out of range settings are refused without sending anything
+        assert!(source.set_frequency(5e9).is_err());
+        assert_eq!(source.tuned_frequency(), None);
+        assert!(source.set_samplerate(-1.0).is_err());
+        source.set_frequency(100e6).unwrap();
+        assert_eq!(source.tuned_frequency(), Some(100e6));
+        source.set_gain(Some(29.7)).unwrap();
+        assert_eq!(source.set_ppm(-3.4).unwrap(), -3.0);
+        let commands = server.join().unwrap();
+        assert_eq!(
+            commands.chunks(5).collect::<Vec<_>>(),
+            [
+                &[CMD_SET_FREQUENCY, 0x05, 0xf5, 0xe1, 0x00][..],
+                &[CMD_SET_GAIN_MODE, 0, 0, 0, 1],
+                &[CMD_SET_GAIN, 0, 0, 0x01, 0x29],
+                &[CMD_SET_FREQ_CORRECTION, 0xff, 0xff, 0xff, 0xfd],
+            ]
+        );
+
+        // The server closing the connection ends the stream
+        assert_eq!(source.read(&mut buffer).unwrap(), ReadStatus::EndOfStream);
+    }
+
+    #[test]
+    fn keeps_samples_aligned_after_a_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut header = b"RTL0".to_vec();
+            header.extend_from_slice(&[0; 8]);
+            stream.write_all(&header).unwrap();
+            // Stall in the middle of the second sample
+            stream.write_all(&[0, 255, 0]).unwrap();
+            std::thread::sleep(READ_TIMEOUT + Duration::from_millis(500));
+            stream.write_all(&[0, 0, 255]).unwrap();
+        });
+
+        let mut source = RtlTcpSource::connect(&address).unwrap();
+        let mut buffer = [Complex32::default(); 1];
+        assert_eq!(source.read(&mut buffer).unwrap(), ReadStatus::Ok);
+        assert_eq!(source.read(&mut buffer).unwrap(), ReadStatus::Overflow);
+        assert_eq!(source.read(&mut buffer).unwrap(), ReadStatus::Ok);
+        assert_eq!(buffer, [Complex32::new(-0.99609375, 0.99609375)]);
+        server.join().unwrap();
+    }
+}
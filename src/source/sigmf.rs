@@ -0,0 +1,238 @@
+use super::{Capabilities, RawSource, ReadStatus, SampleFormat, SampleSource};
+use crate::error::{io, Error, Result};
+use num::complex::Complex32;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+pub const SIGMF_VERSION: &str = "1.0.0";
+
+/// Contents of a `.sigmf-meta` file, restricted to the core namespace
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Metadata {
+    pub global: Global,
+    #[serde(default)]
+    pub captures: Vec<Capture>,
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Global {
+    #[serde(rename = "core:datatype")]
+    pub datatype: String,
+    #[serde(rename = "core:version")]
+    pub version: String,
+    #[serde(rename = "core:sample_rate", skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<f64>,
+    #[serde(rename = "core:hw", skip_serializing_if = "Option::is_none")]
+    pub hw: Option<String>,
+    #[serde(rename = "core:recorder", skip_serializing_if = "Option::is_none")]
+    pub recorder: Option<String>,
+    #[serde(rename = "core:description", skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Capture {
+    #[serde(rename = "core:sample_start")]
+    pub sample_start: u64,
+    #[serde(rename = "core:frequency", skip_serializing_if = "Option::is_none")]
+    pub frequency: Option<f64>,
+    #[serde(rename = "core:datetime", skip_serializing_if = "Option::is_none")]
+    pub datetime: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Annotation {
+    #[serde(rename = "core:sample_start")]
+    pub sample_start: u64,
+    #[serde(rename = "core:sample_count", skip_serializing_if = "Option::is_none")]
+    pub sample_count: Option<u64>,
+    #[serde(
+        rename = "core:freq_lower_edge",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub freq_lower_edge: Option<f64>,
+    #[serde(
+        rename = "core:freq_upper_edge",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub freq_upper_edge: Option<f64>,
+    #[serde(rename = "core:label", skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// Whether `path` names one of the files of a SigMF recording
+pub fn is_sigmf_path(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("sigmf-meta") | Some("sigmf-data")
+    )
+}
+
+/// Paths of the metadata and dataset files of a SigMF recording
+pub fn recording_paths(path: &Path) -> (PathBuf, PathBuf) {
+    (
+        path.with_extension("sigmf-meta"),
+        path.with_extension("sigmf-data"),
+    )
+}
+
+/// Sample format matching a SigMF `core:datatype`, if supported
+pub fn datatype_format(datatype: &str) -> Option<SampleFormat> {
+    match datatype {
+        "cu8" | "cu8_le" | "cu8_be" => Some(SampleFormat::Cu8),
+        "ci8" | "ci8_le" | "ci8_be" => Some(SampleFormat::Cs8),
+        "ci16_le" => Some(SampleFormat::Cs16),
+        "cf32_le" => Some(SampleFormat::Cf32),
+        _ => None,
+    }
+}
+
+/// Playback of a SigMF recording, configured from its metadata
+pub struct SigmfSource {
+    // Reader for the dataset file
+    raw: RawSource,
+    // Recording metadata
+    metadata: Metadata,
+    // Name of the recording
+    name: String,
+    // Number of samples in the dataset, used to wrap around when repeating
+    total_samples: u64,
+    // Index of the next sample to be read
+    position: u64,
+    // Index of the capture segment being played
+    capture: usize,
+    // Frequency ranges of the annotations overlapping the last block
+    active_annotations: Vec<(f64, f64)>,
+}
+
+impl SigmfSource {
+    pub fn open(path: &Path, throttle: bool, repeat: bool) -> Result<Self> {
+        let (meta_path, data_path) = recording_paths(path);
+        let meta_file = File::open(&meta_path).map_err(io(format!(
+            "Cannot open SigMF metadata {}",
+            meta_path.display()
+        )))?;
+        let mut metadata: Metadata = serde_json::from_reader(meta_file)?;
+        let format = datatype_format(&metadata.global.datatype).ok_or_else(|| {
+            Error::Unsupported(format!("Unsupported datatype {}", metadata.global.datatype))
+        })?;
+        metadata.captures.sort_by_key(|c| c.sample_start);
+        metadata.annotations.sort_by_key(|a| a.sample_start);
+
+        let total_samples = std::fs::metadata(&data_path)
+            .map_err(io(format!(
+                "Cannot open SigMF dataset {}",
+                data_path.display()
+            )))?
+            .len()
+            / format.sample_size() as u64;
+        let raw = RawSource::open(&data_path, format, throttle, repeat)?;
+        let name = data_path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        Ok(Self {
+            raw,
+            metadata,
+            name,
+            total_samples,
+            position: 0,
+            capture: 0,
+            active_annotations: Vec::new(),
+        })
+    }
+
+    // Report progress through capture segments and annotations in a block
+    fn advance(&mut self, num_samples: u64) {
+        let start = self.position;
+        let end = start + num_samples;
+
+        while self.capture + 1 < self.metadata.captures.len()
+            && self.metadata.captures[self.capture + 1].sample_start < end
+        {
+            self.capture += 1;
+            if let Some(frequency) = self.frequency() {
+                println!("Capture segment at {} Hz", frequency);
+            }
+        }
+
+        self.active_annotations.clear();
+        for annotation in &self.metadata.annotations {
+            // Without a count the annotation lasts until the end of the recording
+            let annotation_end = annotation.sample_count.map_or(u64::MAX, |count| {
+                annotation.sample_start.saturating_add(count)
+            });
+            if annotation.sample_start < end && annotation_end > start {
+                // Missing edges leave the range open on that side
+                self.active_annotations.push((
+                    annotation.freq_lower_edge.unwrap_or(f64::NEG_INFINITY),
+                    annotation.freq_upper_edge.unwrap_or(f64::INFINITY),
+                ));
+            }
+            if annotation.sample_start >= start && annotation.sample_start < end {
+                println!(
+                    "Annotation {}: {} - {} Hz",
+                    annotation.label.as_deref().unwrap_or("(unlabeled)"),
+                    annotation.freq_lower_edge.unwrap_or(f64::NAN),
+                    annotation.freq_upper_edge.unwrap_or(f64::NAN),
+                );
+            }
+        }
+
+        self.position = end;
+        if self.total_samples > 0 && self.position >= self.total_samples {
+            // Dataset started over
+            self.position %= self.total_samples;
+            self.capture = 0;
+        }
+    }
+}
+
+impl SampleSource for SigmfSource {
+    fn read(&mut self, buffer: &mut [Complex32]) -> Result<ReadStatus> {
+        let status = self.raw.read(buffer)?;
+        if status == ReadStatus::Ok {
+            self.advance(buffer.len() as u64);
+        }
+        Ok(status)
+    }
+
+    fn set_frequency(&mut self, _frequency: f64) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_samplerate(&mut self, samplerate: f64) -> Result<()> {
+        self.raw.set_samplerate(samplerate)
+    }
+
+    fn set_bandwidth(&mut self, _bandwidth: f64) -> Result<()> {
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    fn description(&self) -> String {
+        format!("SigMF recording {}", self.name)
+    }
+
+    fn frequency(&self) -> Option<f64> {
+        self.metadata
+            .captures
+            .get(self.capture)
+            .and_then(|c| c.frequency)
+    }
+
+    fn samplerate(&self) -> Option<f64> {
+        self.metadata.global.sample_rate
+    }
+
+    fn annotations(&self) -> &[(f64, f64)] {
+        &self.active_annotations
+    }
+}
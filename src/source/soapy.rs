@@ -0,0 +1,351 @@
+use super::{Capabilities, GainRange, ReadStatus, SampleSource};
+use crate::error::{soapy, Error, Result};
+use num::complex::Complex32;
+use num::Zero;
+
+/// Live samples from a SoapySDR device
+pub struct SoapySource {
+    // SoapySDR device
+    device: soapysdr::Device,
+    // Receive channels of the device, all opened in one stream so that their
+    // samples stay aligned
+    channels: Vec<usize>,
+    // Source of input samples
+    rx_stream: soapysdr::RxStream<Complex32>,
+    // Samples of the channels other than the first one, when only that one is read
+    spare: Vec<Vec<Complex32>>,
+    // Offset of the RF frontend from the requested frequency, compensated in
+    // baseband by devices that support it
+    lo_offset: f64,
+    // Whether the reference correction was set through the "CORR" frequency
+    // component, which retuning must leave alone
+    frequency_correction: bool,
+}
+
+impl SoapySource {
+    pub fn new(args: &str, channels: &[usize]) -> Result<Self> {
+        let device = soapysdr::Device::new(args).map_err(soapy("No SoapySDR device found"))?;
+
+        let rx_stream: soapysdr::RxStream<Complex32> = device
+            .rx_stream(channels)
+            .map_err(soapy("Cannot open reception stream"))?;
+
+        Ok(Self {
+            device,
+            channels: channels.to_vec(),
+            rx_stream,
+            spare: vec![Vec::new(); channels.len().saturating_sub(1)],
+            lo_offset: 50e3,
+            frequency_correction: false,
+        })
+    }
+}
+
+impl SoapySource {
+    // First channel, the one whose settings are read back
+    fn channel(&self) -> usize {
+        self.channels[0]
+    }
+
+    // Apply a setting to every channel
+    fn each_channel(
+        &self,
+        apply: impl Fn(usize) -> std::result::Result<(), soapysdr::Error>,
+    ) -> std::result::Result<(), soapysdr::Error> {
+        self.channels.iter().try_for_each(|&channel| apply(channel))
+    }
+}
+
+fn gain_range(range: soapysdr::Range) -> GainRange {
+    GainRange {
+        min: range.minimum,
+        max: range.maximum,
+        step: range.step,
+    }
+}
+
+fn format_ranges(ranges: &[soapysdr::Range], unit: f64) -> String {
+    ranges
+        .iter()
+        .map(|r| {
+            if r.minimum == r.maximum {
+                format!("{}", r.minimum / unit)
+            } else {
+                format!("{}-{}", r.minimum / unit, r.maximum / unit)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Print the available SoapySDR devices and their receive capabilities
+pub fn list_devices() -> Result<()> {
+    let direction = soapysdr::Direction::Rx;
+    let devices = soapysdr::enumerate("").map_err(soapy("Cannot enumerate SoapySDR devices"))?;
+    if devices.is_empty() {
+        println!("No SoapySDR devices found");
+    }
+
+    for args in devices {
+        println!("Device: {}", args);
+        let device = match soapysdr::Device::new(args) {
+            Ok(device) => device,
+            Err(error) => {
+                println!("  Cannot open device: {}", error);
+                continue;
+            }
+        };
+
+        let num_channels = device.num_channels(direction).unwrap_or(0);
+        for channel in 0..num_channels {
+            println!("  RX channel {}", channel);
+            if let Ok(antennas) = device.antennas(direction, channel) {
+                println!("    Antennas: {}", antennas.join(", "));
+            }
+            if let Ok(range) = device.gain_range(direction, channel) {
+                println!("    Gain: {} dB", format_ranges(&[range], 1.0));
+            }
+            for name in device.list_gains(direction, channel).unwrap_or_default() {
+                if let Ok(range) = device.gain_element_range(direction, channel, name.as_str()) {
+                    println!("      {}: {} dB", name, format_ranges(&[range], 1.0));
+                }
+            }
+            if let Ok(ranges) = device.frequency_range(direction, channel) {
+                println!("    Frequency: {} MHz", format_ranges(&ranges, 1e6));
+            }
+            if let Ok(ranges) = device.get_sample_rate_range(direction, channel) {
+                println!("    Sample rate: {} MS/s", format_ranges(&ranges, 1e6));
+            }
+            if let Ok(ranges) = device.bandwidth_range(direction, channel) {
+                println!("    Bandwidth: {} MHz", format_ranges(&ranges, 1e6));
+            }
+        }
+    }
+    Ok(())
+}
+
+impl SampleSource for SoapySource {
+    fn read(&mut self, buffer: &mut [Complex32]) -> Result<ReadStatus> {
+        // The other channels have to be read along, their samples are dropped
+        let mut spare = std::mem::take(&mut self.spare);
+        for samples in spare.iter_mut() {
+            samples.resize(buffer.len(), Complex32::zero());
+        }
+        let mut buffers: Vec<&mut [Complex32]> = std::iter::once(buffer)
+            .chain(spare.iter_mut().map(|samples| samples.as_mut_slice()))
+            .collect();
+        let status = self.read_channels(&mut buffers);
+        self.spare = spare;
+        status
+    }
+
+    fn num_channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    fn read_channels(&mut self, buffers: &mut [&mut [Complex32]]) -> Result<ReadStatus> {
+        let length = buffers[0].len();
+        let mut filled = 0;
+        while filled < length {
+            let pieces: Vec<&mut [Complex32]> = buffers
+                .iter_mut()
+                .map(|samples| &mut samples[filled..])
+                .collect();
+            match self.rx_stream.read(&pieces, 5000000) {
+                Ok(n) => filled += n,
+                Err(error) => match error.code {
+                    soapysdr::ErrorCode::Overflow => return Ok(ReadStatus::Overflow),
+                    _ => return Err(soapy("Cannot receive samples")(error)),
+                },
+            }
+        }
+        Ok(ReadStatus::Ok)
+    }
+
+    fn set_frequency(&mut self, frequency: f64) -> Result<()> {
+        let mut tuning_args = format!("OFFSET={}", self.lo_offset);
+        if self.frequency_correction {
+            tuning_args += ",CORR=IGNORE";
+        }
+        self.each_channel(|channel| {
+            self.device.set_frequency(
+                soapysdr::Direction::Rx,
+                channel,
+                frequency,
+                tuning_args.as_str(),
+            )
+        })
+        .map_err(soapy("Cannot set frequency"))
+    }
+
+    fn set_lo_offset(&mut self, offset: f64) -> Result<()> {
+        self.lo_offset = offset;
+        Ok(())
+    }
+
+    fn set_ppm(&mut self, ppm: f64) -> Result<f64> {
+        let direction = soapysdr::Direction::Rx;
+        let components = self
+            .device
+            .list_frequencies(direction, self.channel())
+            .unwrap_or_default();
+        if !components.iter().any(|name| name == "CORR") {
+            return Err(Error::Unsupported(String::from(
+                "Frequency correction not supported by this device",
+            )));
+        }
+        self.each_channel(|channel| {
+            self.device
+                .set_component_frequency(direction, channel, "CORR", ppm, ())
+        })
+        .map_err(soapy("Cannot set frequency correction"))?;
+        self.frequency_correction = true;
+        Ok(ppm)
+    }
+
+    fn set_dc_offset_mode(&mut self, automatic: bool) -> Result<()> {
+        let direction = soapysdr::Direction::Rx;
+        if !self
+            .device
+            .has_dc_offset_mode(direction, self.channel())
+            .unwrap_or(false)
+        {
+            return Err(Error::Unsupported(String::from(
+                "DC offset removal not supported by this device",
+            )));
+        }
+        self.each_channel(|channel| {
+            self.device
+                .set_dc_offset_mode(direction, channel, automatic)
+        })
+        .map_err(soapy("Cannot set DC offset mode"))
+    }
+
+    fn tuned_frequency(&self) -> Option<f64> {
+        self.device
+            .frequency(soapysdr::Direction::Rx, self.channel())
+            .ok()
+    }
+
+    fn set_samplerate(&mut self, samplerate: f64) -> Result<()> {
+        self.each_channel(|channel| {
+            self.device
+                .set_sample_rate(soapysdr::Direction::Rx, channel, samplerate)
+        })
+        .map_err(soapy("Cannot set sample rate"))
+    }
+
+    fn set_bandwidth(&mut self, bandwidth: f64) -> Result<()> {
+        self.each_channel(|channel| {
+            self.device
+                .set_bandwidth(soapysdr::Direction::Rx, channel, bandwidth)
+        })
+        .map_err(soapy("Cannot set bandwidth"))
+    }
+
+    fn set_gain(&mut self, gain: Option<f64>) -> Result<()> {
+        let direction = soapysdr::Direction::Rx;
+        let has_agc = self
+            .device
+            .has_gain_mode(direction, self.channel())
+            .unwrap_or(false);
+        match gain {
+            Some(gain) => {
+                if has_agc {
+                    self.each_channel(|channel| {
+                        self.device.set_gain_mode(direction, channel, false)
+                    })
+                    .map_err(soapy("Cannot disable automatic gain"))?;
+                }
+                self.each_channel(|channel| self.device.set_gain(direction, channel, gain))
+                    .map_err(soapy("Cannot set gain"))
+            }
+            None if has_agc => self
+                .each_channel(|channel| self.device.set_gain_mode(direction, channel, true))
+                .map_err(soapy("Cannot enable automatic gain")),
+            None => Err(Error::Unsupported(String::from(
+                "Automatic gain not supported by this device",
+            ))),
+        }
+    }
+
+    fn activate(&mut self) -> Result<()> {
+        self.rx_stream
+            .activate(None)
+            .map_err(soapy("Cannot activate reception stream"))
+    }
+
+    fn set_gain_element(&mut self, name: &str, gain: f64) -> Result<()> {
+        let direction = soapysdr::Direction::Rx;
+        if self
+            .device
+            .has_gain_mode(direction, self.channel())
+            .unwrap_or(false)
+        {
+            self.each_channel(|channel| self.device.set_gain_mode(direction, channel, false))
+                .map_err(soapy("Cannot disable automatic gain"))?;
+        }
+        self.each_channel(|channel| self.device.set_gain_element(direction, channel, name, gain))
+            .map_err(soapy("Cannot set gain element"))
+    }
+
+    fn gain(&self) -> Option<f64> {
+        self.device
+            .gain(soapysdr::Direction::Rx, self.channel())
+            .ok()
+    }
+
+    fn gain_element(&self, name: &str) -> Option<f64> {
+        self.device
+            .gain_element(soapysdr::Direction::Rx, self.channel(), name)
+            .ok()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let direction = soapysdr::Direction::Rx;
+        let frequency_ranges = self
+            .device
+            .frequency_range(direction, self.channel())
+            .unwrap_or_default()
+            .iter()
+            .map(|r| (r.minimum, r.maximum))
+            .collect();
+        let gain_elements = self
+            .device
+            .list_gains(direction, self.channel())
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|name| {
+                let range = self
+                    .device
+                    .gain_element_range(direction, self.channel(), name.as_str())
+                    .ok()?;
+                Some((name, gain_range(range)))
+            })
+            .collect();
+        Capabilities {
+            tunable: true,
+            frequency_ranges,
+            gain: self
+                .device
+                .gain_range(direction, self.channel())
+                .ok()
+                .map(gain_range),
+            gain_elements,
+            agc: self
+                .device
+                .has_gain_mode(direction, self.channel())
+                .unwrap_or(false),
+        }
+    }
+
+    fn description(&self) -> String {
+        self.device
+            .hardware_key()
+            .map(|key| {
+                let channels: Vec<String> = self.channels.iter().map(|c| c.to_string()).collect();
+                format!("{} channel {}", key, channels.join(", "))
+            })
+            .unwrap_or_else(|_| String::from("SoapySDR"))
+    }
+}
@@ -0,0 +1,59 @@
+use crate::units::parse_frequency;
+use std::fmt;
+use std::str::FromStr;
+
+/// Frequency range covered by stepping the tuner, stitching the spectra side by side
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sweep {
+    // Lower edge of the range in Hz
+    pub start: f64,
+    // Upper edge of the range in Hz
+    pub stop: f64,
+    // Fraction of each FFT kept around its centre, the edges suffer from filter roll-off
+    pub keep: f64,
+    // Time to discard after each retune while the tuner settles, in seconds
+    pub settle_time: f64,
+}
+
+impl Sweep {
+    /// Number of bins kept from each FFT, an even number so the pieces line up
+    pub fn kept_bins(&self, fft_size: usize) -> usize {
+        (((fft_size as f64 * self.keep) as usize) & !1).clamp(2, fft_size)
+    }
+
+    /// Tuning frequencies of the steps, each contributing `kept_bins` bins
+    pub fn centers(&self, samplerate: f64, fft_size: usize) -> Vec<f64> {
+        let step = self.kept_bins(fft_size) as f64 * samplerate / fft_size as f64;
+        let steps = ((self.stop - self.start) / step).ceil().max(1.0) as usize;
+        (0..steps)
+            .map(|i| self.start + step * (i as f64 + 0.5))
+            .collect()
+    }
+}
+
+impl FromStr for Sweep {
+    type Err = String;
+
+    /// Parse `START:STOP`, each accepting a k, M or G suffix
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, stop) = s
+            .split_once(':')
+            .ok_or_else(|| String::from("expected START:STOP"))?;
+        let (start, stop) = (parse_frequency(start)?, parse_frequency(stop)?);
+        if stop <= start {
+            return Err(String::from("stop frequency must be above start"));
+        }
+        Ok(Self {
+            start,
+            stop,
+            keep: 0.75,
+            settle_time: 0.002,
+        })
+    }
+}
+
+impl fmt::Display for Sweep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.3}-{:.3} MHz", self.start / 1e6, self.stop / 1e6)
+    }
+}
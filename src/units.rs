@@ -0,0 +1,58 @@
+/// Parse a frequency in Hz with an optional k, M or G multiplier, e.g. "433.92M" or "2.4GHz"
+pub fn parse_frequency(s: &str) -> Result<f64, String> {
+    let text = s.trim();
+    let text = text
+        .strip_suffix("Hz")
+        .or_else(|| text.strip_suffix("hz"))
+        .unwrap_or(text)
+        .trim_end();
+    let (number, multiplier) = match text.chars().last() {
+        Some('k' | 'K') => (&text[..text.len() - 1], 1e3),
+        Some('m' | 'M') => (&text[..text.len() - 1], 1e6),
+        Some('g' | 'G') => (&text[..text.len() - 1], 1e9),
+        _ => (text, 1.0),
+    };
+    number
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .map(|value| value * multiplier)
+        .filter(|value| value.is_finite())
+        .ok_or_else(|| format!("invalid frequency {:?}", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_parses(text: &str, expected: f64) {
+        let value = parse_frequency(text).unwrap();
+        assert!(
+            (value - expected).abs() < 1e-3,
+            "{:?} parsed as {}, expected {}",
+            text,
+            value,
+            expected
+        );
+    }
+
+    #[test]
+    fn parses_multipliers_and_units() {
+        assert_parses("1000", 1e3);
+        assert_parses("-2.5", -2.5);
+        assert_parses("100k", 100e3);
+        assert_parses("433.92M", 433.92e6);
+        assert_parses("2.4GHz", 2.4e9);
+        assert_parses("1.5 MHz", 1.5e6);
+        assert_parses(" 7 khz ", 7e3);
+        assert_parses("1e6", 1e6);
+        assert_parses("50Hz", 50.0);
+    }
+
+    #[test]
+    fn rejects_malformed_frequencies() {
+        for text in ["", "M", "MHz", "abc", "1.2.3M", "12X", "inf", "NaN"] {
+            assert!(parse_frequency(text).is_err(), "{:?} was accepted", text);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use num::complex::Complex32;
+use std::f64::consts::TAU;
+use std::fmt;
+use std::str::FromStr;
+
+const DEFAULT_KAISER_BETA: f64 = 8.6;
+
+/// Tapering applied to each block before the FFT
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WindowFunction {
+    Rectangular,
+    Hann,
+    Hamming,
+    BlackmanHarris,
+    FlatTop,
+    Kaiser(f64),
+}
+
+impl WindowFunction {
+    /// Next window in the runtime selection cycle
+    pub fn next(&self) -> Self {
+        match self {
+            WindowFunction::Rectangular => WindowFunction::Hann,
+            WindowFunction::Hann => WindowFunction::Hamming,
+            WindowFunction::Hamming => WindowFunction::BlackmanHarris,
+            WindowFunction::BlackmanHarris => WindowFunction::FlatTop,
+            WindowFunction::FlatTop => WindowFunction::Kaiser(DEFAULT_KAISER_BETA),
+            WindowFunction::Kaiser(_) => WindowFunction::Rectangular,
+        }
+    }
+
+    // Value of the window at position n of a periodic window of length size
+    fn value(&self, n: usize, size: usize) -> f64 {
+        let x = TAU * n as f64 / size as f64;
+        // Sum of cosine terms with alternating signs
+        let cosine_sum = |a: &[f64]| -> f64 {
+            a.iter()
+                .enumerate()
+                .map(|(k, a)| if k % 2 == 0 { 1.0 } else { -1.0 } * a * (k as f64 * x).cos())
+                .sum()
+        };
+        match self {
+            WindowFunction::Rectangular => 1.0,
+            WindowFunction::Hann => cosine_sum(&[0.5, 0.5]),
+            WindowFunction::Hamming => cosine_sum(&[0.54, 0.46]),
+            WindowFunction::BlackmanHarris => cosine_sum(&[0.35875, 0.48829, 0.14128, 0.01168]),
+            WindowFunction::FlatTop => cosine_sum(&[
+                0.21557895,
+                0.41663158,
+                0.277263158,
+                0.083578947,
+                0.006947368,
+            ]),
+            WindowFunction::Kaiser(beta) => {
+                let r = 2.0 * n as f64 / size as f64 - 1.0;
+                bessel_i0(beta * (1.0 - r * r).max(0.0).sqrt()) / bessel_i0(*beta)
+            }
+        }
+    }
+}
+
+impl fmt::Display for WindowFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WindowFunction::Rectangular => write!(f, "rectangular"),
+            WindowFunction::Hann => write!(f, "hann"),
+            WindowFunction::Hamming => write!(f, "hamming"),
+            WindowFunction::BlackmanHarris => write!(f, "blackman-harris"),
+            WindowFunction::FlatTop => write!(f, "flat-top"),
+            WindowFunction::Kaiser(beta) => write!(f, "kaiser:{}", beta),
+        }
+    }
+}
+
+impl FromStr for WindowFunction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("kaiser", beta)) => beta
+                .parse()
+                .map(WindowFunction::Kaiser)
+                .map_err(|_| format!("invalid kaiser beta {:?}", beta)),
+            None => match s {
+                "rectangular" => Ok(WindowFunction::Rectangular),
+                "hann" => Ok(WindowFunction::Hann),
+                "hamming" => Ok(WindowFunction::Hamming),
+                "blackman-harris" => Ok(WindowFunction::BlackmanHarris),
+                "flat-top" => Ok(WindowFunction::FlatTop),
+                "kaiser" => Ok(WindowFunction::Kaiser(DEFAULT_KAISER_BETA)),
+                _ => Err(format!("unknown window {:?}", s)),
+            },
+            _ => Err(format!("unknown window {:?}", s)),
+        }
+    }
+}
+
+/// Power correction applied after windowing
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowScaling {
+    /// Keep the level of narrowband signals (coherent gain correction)
+    Amplitude,
+    /// Keep the level of the noise floor (coherent gain and ENBW correction)
+    Noise,
+}
+
+// Zeroth order modified Bessel function of the first kind
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half_x = x / 2.0;
+    for k in 1..50 {
+        term *= (half_x / k as f64) * (half_x / k as f64);
+        sum += term;
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+    sum
+}
+
+/// Precomputed window coefficients for a given FFT size
+pub struct Window {
+    function: WindowFunction,
+    coefficients: Vec<f32>,
+    // Mean of the coefficients, attenuation of a coherent tone
+    coherent_gain: f64,
+    // Equivalent noise bandwidth in bins
+    enbw: f64,
+}
+
+impl Window {
+    pub fn new(function: WindowFunction, size: usize) -> Self {
+        let coefficients: Vec<f64> = (0..size).map(|n| function.value(n, size)).collect();
+        let sum: f64 = coefficients.iter().sum();
+        let sum_squares: f64 = coefficients.iter().map(|w| w * w).sum();
+
+        Self {
+            function,
+            coefficients: coefficients.iter().map(|&w| w as f32).collect(),
+            coherent_gain: sum / size as f64,
+            enbw: size as f64 * sum_squares / (sum * sum),
+        }
+    }
+
+    pub fn function(&self) -> WindowFunction {
+        self.function
+    }
+
+    pub fn coherent_gain(&self) -> f64 {
+        self.coherent_gain
+    }
+
+    pub fn enbw(&self) -> f64 {
+        self.enbw
+    }
+
+    /// Factor that makes FFT power comparable across windows
+    pub fn power_correction(&self, scaling: WindowScaling) -> f64 {
+        let amplitude = 1.0 / (self.coherent_gain * self.coherent_gain);
+        match scaling {
+            WindowScaling::Amplitude => amplitude,
+            WindowScaling::Noise => amplitude / self.enbw,
+        }
+    }
+
+    pub fn apply(&self, buffer: &mut [Complex32]) {
+        for (sample, w) in buffer.iter_mut().zip(&self.coefficients) {
+            *sample *= *w;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(value: f64, expected: f64) {
+        assert!(
+            (value - expected).abs() < 1e-3,
+            "got {}, expected {}",
+            value,
+            expected
+        );
+    }
+
+    #[test]
+    fn coherent_gain_and_enbw_match_reference_values() {
+        // Values for periodic windows, from Harris' "On the use of windows" tables
+        for (function, coherent_gain, enbw) in [
+            (WindowFunction::Rectangular, 1.0, 1.0),
+            (WindowFunction::Hann, 0.5, 1.5),
+            (WindowFunction::Hamming, 0.54, 1.3628),
+            (WindowFunction::BlackmanHarris, 0.35875, 2.0044),
+        ] {
+            let window = Window::new(function, 1024);
+            assert_close(window.coherent_gain(), coherent_gain);
+            assert_close(window.enbw(), enbw);
+        }
+    }
+
+    #[test]
+    fn power_correction_undoes_window_losses() {
+        let window = Window::new(WindowFunction::Hann, 256);
+        assert_close(window.power_correction(WindowScaling::Amplitude), 4.0);
+        assert_close(window.power_correction(WindowScaling::Noise), 4.0 / 1.5);
+    }
+}